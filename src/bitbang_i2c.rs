@@ -0,0 +1,752 @@
+//! Software I2C controller bit-banging [`embedded-hal`] digital pins
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::{InputPin, OutputPin, PinState};
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use crate::Delay;
+
+/// Frequency of a new [`BitBangI2c`], in Hz
+const DEFAULT_FREQUENCY: u32 = 100_000;
+
+/// Longest time a device may hold SCL low to stretch the clock, the SMBus clock low timeout
+const CLOCK_STRETCH_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// I2C controller driving two GPIO pins, for boards without a free I2C adapter
+///
+/// Implements [`I2c`] with 7-bit addresses, generating start, repeated start and stop
+/// conditions and checking acknowledges in software. Both pins must be open-drain outputs,
+/// so that driving them high releases the line to its pull-up, and must be readable: SDA to
+/// receive data and acknowledges, SCL to let devices stretch the clock by holding it low.
+/// A device stretching it for longer than 25 ms makes the transaction fail. Driving SDA high
+/// and reading it back low while sending means another controller is using the bus, which is
+/// reported as an arbitration loss. Addresses above 0x7f are rejected before using the bus.
+///
+/// Timing relies on [`Delay`], and every edge takes a system call, so the clock is at most
+/// the [configured frequency](BitBangI2c::with_frequency), and usually slower: each half
+/// period lasts at least the sleep latency of the system, tens of microseconds, unless the
+/// delay is [calibrated]. [`BitBangI2c::set_frequency`] accounts for the time taken by pin
+/// accesses and reports the frequency actually achievable. The thread may also be preempted
+/// in the middle of a transfer, which I2C devices tolerate since the controller drives the
+/// clock. An I2C adapter of the kernel, such as `i2c-gpio`, is preferable when it can be set
+/// up.
+///
+/// ```no_run
+/// # #[cfg(feature = "gpio_cdev")] {
+/// use embedded_hal::i2c::I2c;
+/// use gpio_cdev::{Chip, LineRequestFlags};
+/// use linux_embedded_hal::{BitBangI2c, CdevPin};
+///
+/// let mut chip = Chip::new("/dev/gpiochip0").unwrap();
+/// let flags = LineRequestFlags::OUTPUT | LineRequestFlags::OPEN_DRAIN;
+/// let scl = CdevPin::new(chip.get_line(2).unwrap().request(flags.clone(), 1, "i2c").unwrap());
+/// let sda = CdevPin::new(chip.get_line(3).unwrap().request(flags, 1, "i2c").unwrap());
+///
+/// let mut i2c = BitBangI2c::new(scl.unwrap(), sda.unwrap());
+/// let mut id = [0];
+/// i2c.write_read(0x48, &[0x0f], &mut id).unwrap();
+/// # }
+/// ```
+///
+/// [calibrated]: crate::Delay::calibrate
+pub struct BitBangI2c<SCL, SDA> {
+    scl: SCL,
+    sda: SDA,
+    half_period: Duration,
+}
+
+impl<SCL, SDA, E> BitBangI2c<SCL, SDA>
+where
+    SCL: InputPin<Error = E> + OutputPin<Error = E>,
+    SDA: InputPin<Error = E> + OutputPin<Error = E>,
+{
+    /// Drive the bus with the `scl` and `sda` pins, at 100 kHz
+    pub fn new(scl: SCL, sda: SDA) -> Self {
+        BitBangI2c {
+            scl,
+            sda,
+            half_period: half_period(DEFAULT_FREQUENCY),
+        }
+    }
+
+    /// Set the clock frequency in Hz, an upper bound on the actual frequency
+    ///
+    /// Pin accesses and the delay overshoot come on top of each half period, so the clock
+    /// is slower than `hz`; see [`BitBangI2c::set_frequency`] to compensate for that.
+    pub fn with_frequency(mut self, hz: u32) -> Self {
+        self.half_period = half_period(hz);
+        self
+    }
+
+    /// Set the clock frequency as close to `hz` as the system allows, and return the
+    /// estimated actual frequency in Hz.
+    ///
+    /// Every half period of the clock is made of a few pin accesses and a delay. This times
+    /// pin accesses, by reading SCL, which doesn't disturb the bus, and shortens the delay by
+    /// the time they take. It then times the resulting delay, which includes the overshoot
+    /// of [`Delay`], large unless [calibrated], to estimate the frequency achieved. On Linux
+    /// without realtime scheduling, this is commonly well below 100 kHz with GPIO character
+    /// devices, so compare the result with what the devices on the bus need. The estimate
+    /// doesn't include preemption, which makes some clock periods longer, but is harmless.
+    ///
+    /// The result depends on the system load at the time of the call, and takes a few
+    /// milliseconds to measure.
+    ///
+    /// [calibrated]: crate::Delay::calibrate
+    pub fn set_frequency(&mut self, hz: u32) -> Result<u32, BitBangI2cError<E>> {
+        let mut samples = Vec::with_capacity(TIMING_SAMPLES);
+        for _ in 0..TIMING_SAMPLES {
+            let start = Instant::now();
+            self.scl.is_low().map_err(BitBangI2cError::Pin)?;
+            samples.push(start.elapsed());
+        }
+        let overhead = median(&mut samples) * PIN_ACCESSES_PER_HALF_PERIOD;
+
+        self.half_period = half_period(hz).saturating_sub(overhead);
+        samples.clear();
+        for _ in 0..TIMING_SAMPLES {
+            let start = Instant::now();
+            self.delay();
+            samples.push(start.elapsed());
+        }
+        let actual = median(&mut samples) + overhead;
+        Ok(frequency(actual).min(hz))
+    }
+
+    /// Release the pins
+    pub fn release(self) -> (SCL, SDA) {
+        (self.scl, self.sda)
+    }
+
+    /// Send the operations of a transaction, without the final stop condition.
+    fn run(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), BitBangI2cError<E>> {
+        for i in 0..operations.len() {
+            let read = matches!(operations[i], Operation::Read(_));
+            // Adjacent operations of the same kind are merged, without a repeated start
+            let starts_group = i == 0 || matches!(operations[i - 1], Operation::Read(_)) != read;
+            if starts_group {
+                self.start()?;
+                if !self.write_byte(address << 1 | u8::from(read))? {
+                    return Err(BitBangI2cError::NoAcknowledge(NoAcknowledgeSource::Address));
+                }
+            }
+            let ends_group = ends_group(operations, i);
+            match &mut operations[i] {
+                Operation::Write(buf) => {
+                    for &byte in buf.iter() {
+                        if !self.write_byte(byte)? {
+                            return Err(BitBangI2cError::NoAcknowledge(NoAcknowledgeSource::Data));
+                        }
+                    }
+                }
+                Operation::Read(buf) => {
+                    let len = buf.len();
+                    for (j, byte) in buf.iter_mut().enumerate() {
+                        // The last byte read before a stop or repeated start is not acknowledged
+                        *byte = self.read_byte(!(ends_group && j == len - 1))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a start condition, or a repeated start if SCL is low.
+    fn start(&mut self) -> Result<(), BitBangI2cError<E>> {
+        self.sda.set_high().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.sda.set_low().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.scl.set_low().map_err(BitBangI2cError::Pin)
+    }
+
+    /// Send a stop condition, leaving the bus idle.
+    fn stop(&mut self) -> Result<(), BitBangI2cError<E>> {
+        self.sda.set_low().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.sda.set_high().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        Ok(())
+    }
+
+    /// Send `byte`, most significant bit first, and return whether it was acknowledged.
+    fn write_byte(&mut self, byte: u8) -> Result<bool, BitBangI2cError<E>> {
+        for bit in (0..8).rev() {
+            self.write_bit(byte >> bit & 1 != 0)?;
+        }
+        Ok(!self.read_bit()?)
+    }
+
+    /// Receive a byte, then acknowledge it if `ack`.
+    fn read_byte(&mut self, ack: bool) -> Result<u8, BitBangI2cError<E>> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = byte << 1 | u8::from(self.read_bit()?);
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), BitBangI2cError<E>> {
+        self.sda
+            .set_state(PinState::from(bit))
+            .map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.release_scl()?;
+        if bit && self.sda.is_low().map_err(BitBangI2cError::Pin)? {
+            return Err(BitBangI2cError::ArbitrationLoss);
+        }
+        self.delay();
+        self.scl.set_low().map_err(BitBangI2cError::Pin)
+    }
+
+    fn read_bit(&mut self) -> Result<bool, BitBangI2cError<E>> {
+        self.sda.set_high().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.release_scl()?;
+        let bit = self.sda.is_high().map_err(BitBangI2cError::Pin)?;
+        self.delay();
+        self.scl.set_low().map_err(BitBangI2cError::Pin)?;
+        Ok(bit)
+    }
+
+    /// Release SCL and wait for it to go high, as devices may hold it low to stretch the
+    /// clock.
+    fn release_scl(&mut self) -> Result<(), BitBangI2cError<E>> {
+        self.scl.set_high().map_err(BitBangI2cError::Pin)?;
+        let start = Instant::now();
+        while self.scl.is_low().map_err(BitBangI2cError::Pin)? {
+            if start.elapsed() > CLOCK_STRETCH_TIMEOUT {
+                return Err(BitBangI2cError::ClockStretchTimeout);
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    fn delay(&self) {
+        if !self.half_period.is_zero() {
+            Delay::sleep(self.half_period);
+        }
+    }
+}
+
+/// Number of measurements [`BitBangI2c::set_frequency`] takes the median of
+const TIMING_SAMPLES: usize = 11;
+
+/// Average number of pin accesses in each half period of the clock while sending or
+/// receiving a bit
+const PIN_ACCESSES_PER_HALF_PERIOD: u32 = 2;
+
+/// Half of the clock period at `hz`
+fn half_period(hz: u32) -> Duration {
+    Duration::from_nanos(500_000_000 / u64::from(hz.max(1)))
+}
+
+/// Clock frequency whose half period is `half_period`, in Hz
+fn frequency(half_period: Duration) -> u32 {
+    let nanos = half_period.as_nanos().max(1);
+    u32::try_from(500_000_000 / nanos).unwrap_or(u32::MAX)
+}
+
+/// Median of `samples`, which must not be empty
+fn median(samples: &mut [Duration]) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+/// Whether no byte is transferred after operation `i` before the next start or stop
+/// condition, i.e. no later operation of the same kind follows without one in between.
+fn ends_group(operations: &[Operation<'_>], i: usize) -> bool {
+    let read = matches!(operations[i], Operation::Read(_));
+    operations[i + 1..]
+        .iter()
+        .take_while(|op| matches!(op, Operation::Read(_)) == read)
+        .all(|op| match op {
+            Operation::Read(buf) => buf.is_empty(),
+            Operation::Write(buf) => buf.is_empty(),
+        })
+}
+
+impl<SCL, SDA, E> ErrorType for BitBangI2c<SCL, SDA>
+where
+    SCL: InputPin<Error = E> + OutputPin<Error = E>,
+    SDA: InputPin<Error = E> + OutputPin<Error = E>,
+    E: fmt::Debug,
+{
+    type Error = BitBangI2cError<E>;
+}
+
+impl<SCL, SDA, E> I2c for BitBangI2c<SCL, SDA>
+where
+    SCL: InputPin<Error = E> + OutputPin<Error = E>,
+    SDA: InputPin<Error = E> + OutputPin<Error = E>,
+    E: fmt::Debug,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address > 0x7f {
+            return Err(BitBangI2cError::InvalidAddress(address));
+        }
+        if operations.is_empty() {
+            return Ok(());
+        }
+        match self.run(address, operations) {
+            // The bus is no longer ours to stop, or SCL is stuck
+            Err(
+                err @ (BitBangI2cError::ArbitrationLoss | BitBangI2cError::ClockStretchTimeout),
+            ) => Err(err),
+            res => {
+                let stop = self.stop();
+                res.and(stop)
+            }
+        }
+    }
+}
+
+/// Error of a [`BitBangI2c`] transaction
+#[derive(Debug)]
+pub enum BitBangI2cError<E> {
+    /// Driving or reading a pin failed
+    Pin(E),
+    /// The device didn't acknowledge its address or some data
+    NoAcknowledge(NoAcknowledgeSource),
+    /// Another controller took over the bus
+    ArbitrationLoss,
+    /// A device held SCL low for too long
+    ClockStretchTimeout,
+    /// The address doesn't fit in 7 bits, so nothing was sent
+    InvalidAddress(u8),
+}
+
+impl<E: fmt::Display> fmt::Display for BitBangI2cError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitBangI2cError::Pin(err) => write!(f, "{}", err),
+            BitBangI2cError::NoAcknowledge(source) => write!(f, "{}", source),
+            BitBangI2cError::ArbitrationLoss => write!(f, "arbitration lost"),
+            BitBangI2cError::ClockStretchTimeout => write!(f, "clock stretched for too long"),
+            BitBangI2cError::InvalidAddress(address) => {
+                write!(f, "address {:#x} is not a 7-bit address", address)
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for BitBangI2cError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BitBangI2cError::Pin(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<E: fmt::Debug> embedded_hal::i2c::Error for BitBangI2cError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            BitBangI2cError::Pin(_) => ErrorKind::Other,
+            BitBangI2cError::NoAcknowledge(source) => ErrorKind::NoAcknowledge(*source),
+            BitBangI2cError::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+            BitBangI2cError::ClockStretchTimeout => ErrorKind::Bus,
+            BitBangI2cError::InvalidAddress(_) => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::i2c::Error;
+    use std::cell::RefCell;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Event {
+        Start,
+        Address(u8),
+        Write(u8),
+        Read(u8),
+        Stop,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Phase {
+        Idle,
+        /// Shifting in a byte from the controller
+        Receive,
+        /// Acknowledging a received byte
+        Ack,
+        /// Shifting out a byte to the controller
+        Transmit,
+        /// Reading the acknowledge of the controller
+        ControllerAck,
+    }
+
+    /// Bus with a single device at `address`, which answers reads with an incrementing
+    /// counter
+    struct Bus {
+        /// Levels driven by the controller
+        scl: bool,
+        sda: bool,
+        /// Level driven by the device
+        device_sda: bool,
+        /// Number of SCL reads the device holds the clock low for after each release
+        stretch: usize,
+        stretching: usize,
+        address: u8,
+        phase: Phase,
+        reading: bool,
+        address_byte: bool,
+        byte: u8,
+        bits: u32,
+        acked: bool,
+        counter: u8,
+        log: Vec<Event>,
+    }
+
+    impl Bus {
+        fn new(address: u8, stretch: usize) -> RefCell<Self> {
+            RefCell::new(Bus {
+                scl: true,
+                sda: true,
+                device_sda: true,
+                stretch,
+                stretching: 0,
+                address,
+                phase: Phase::Idle,
+                reading: false,
+                address_byte: false,
+                byte: 0,
+                bits: 0,
+                acked: false,
+                counter: 0xa0,
+                log: Vec::new(),
+            })
+        }
+
+        fn sda_line(&self) -> bool {
+            self.sda && self.device_sda
+        }
+
+        fn drive_sda(&mut self, level: bool) {
+            let before = self.sda_line();
+            self.sda = level;
+            let after = self.sda_line();
+            if self.scl && before != after {
+                if after {
+                    self.log.push(Event::Stop);
+                    self.phase = Phase::Idle;
+                } else {
+                    self.log.push(Event::Start);
+                    self.phase = Phase::Receive;
+                    self.address_byte = true;
+                    self.byte = 0;
+                    self.bits = 0;
+                }
+                self.device_sda = true;
+            }
+        }
+
+        fn rise(&mut self) {
+            match self.phase {
+                Phase::Receive => {
+                    self.byte = self.byte << 1 | u8::from(self.sda_line());
+                    self.bits += 1;
+                }
+                Phase::ControllerAck => self.acked = !self.sda_line(),
+                _ => {}
+            }
+        }
+
+        fn fall(&mut self) {
+            match self.phase {
+                Phase::Receive if self.bits == 8 => {
+                    if self.address_byte {
+                        self.address_byte = false;
+                        self.log.push(Event::Address(self.byte));
+                        if self.byte >> 1 != self.address {
+                            self.phase = Phase::Idle;
+                            return;
+                        }
+                        self.reading = self.byte & 1 != 0;
+                    } else {
+                        self.log.push(Event::Write(self.byte));
+                    }
+                    self.device_sda = false;
+                    self.phase = Phase::Ack;
+                }
+                Phase::Ack if self.reading => self.transmit_next(),
+                Phase::Ack => {
+                    self.device_sda = true;
+                    self.byte = 0;
+                    self.bits = 0;
+                    self.phase = Phase::Receive;
+                }
+                Phase::Transmit => {
+                    self.bits += 1;
+                    if self.bits == 8 {
+                        self.device_sda = true;
+                        self.phase = Phase::ControllerAck;
+                    } else {
+                        self.device_sda = self.byte << self.bits & 0x80 != 0;
+                    }
+                }
+                Phase::ControllerAck => {
+                    self.log.push(Event::Read(self.byte));
+                    if self.acked {
+                        self.transmit_next();
+                    } else {
+                        self.phase = Phase::Idle;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn transmit_next(&mut self) {
+            self.byte = self.counter;
+            self.counter += 1;
+            self.bits = 0;
+            self.device_sda = self.byte & 0x80 != 0;
+            self.phase = Phase::Transmit;
+        }
+    }
+
+    struct Scl<'a>(&'a RefCell<Bus>);
+    struct Sda<'a>(&'a RefCell<Bus>);
+
+    impl embedded_hal::digital::ErrorType for Scl<'_> {
+        type Error = Infallible;
+    }
+
+    impl embedded_hal::digital::ErrorType for Sda<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Scl<'_> {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            let mut bus = self.0.borrow_mut();
+            if bus.scl {
+                bus.scl = false;
+                bus.fall();
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            let mut bus = self.0.borrow_mut();
+            if !bus.scl {
+                bus.scl = true;
+                bus.stretching = bus.stretch;
+                bus.rise();
+            }
+            Ok(())
+        }
+    }
+
+    impl InputPin for Scl<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            let mut bus = self.0.borrow_mut();
+            if bus.stretching > 0 {
+                bus.stretching -= 1;
+                return Ok(false);
+            }
+            Ok(bus.scl)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    impl OutputPin for Sda<'_> {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().drive_sda(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().drive_sda(true);
+            Ok(())
+        }
+    }
+
+    impl InputPin for Sda<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.0.borrow().sda_line())
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.0.borrow().sda_line())
+        }
+    }
+
+    fn i2c(bus: &RefCell<Bus>) -> BitBangI2c<Scl<'_>, Sda<'_>> {
+        BitBangI2c::new(Scl(bus), Sda(bus)).with_frequency(1_000_000)
+    }
+
+    #[test]
+    fn test_write_read() {
+        use Event::*;
+
+        let bus = Bus::new(0x48, 0);
+        let mut read = [0; 2];
+        i2c(&bus).write_read(0x48, &[1, 2], &mut read).unwrap();
+        assert_eq!(read, [0xa0, 0xa1]);
+        assert_eq!(
+            bus.borrow().log,
+            [
+                Start,
+                Address(0x90),
+                Write(1),
+                Write(2),
+                Start,
+                Address(0x91),
+                Read(0xa0),
+                Read(0xa1),
+                Stop
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merged_operations() {
+        use Event::*;
+
+        let bus = Bus::new(0x48, 0);
+        let (mut a, mut b) = ([0; 1], [0; 1]);
+        i2c(&bus)
+            .transaction(
+                0x48,
+                &mut [
+                    Operation::Write(&[1]),
+                    Operation::Write(&[2]),
+                    Operation::Read(&mut a),
+                    Operation::Read(&mut b),
+                    Operation::Read(&mut []),
+                ],
+            )
+            .unwrap();
+        assert_eq!((a, b), ([0xa0], [0xa1]));
+        assert_eq!(
+            bus.borrow().log,
+            [
+                Start,
+                Address(0x90),
+                Write(1),
+                Write(2),
+                Start,
+                Address(0x91),
+                Read(0xa0),
+                Read(0xa1),
+                Stop
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_acknowledge() {
+        let bus = Bus::new(0x48, 0);
+        let err = i2c(&bus).write(0x49, &[1]).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        assert_eq!(
+            bus.borrow().log,
+            [Event::Start, Event::Address(0x92), Event::Stop]
+        );
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        let bus = Bus::new(0x48, 0);
+        let err = i2c(&bus).write(0xc8, &[1]).unwrap_err();
+        assert!(matches!(err, BitBangI2cError::InvalidAddress(0xc8)));
+        assert!(bus.borrow().log.is_empty());
+    }
+
+    #[test]
+    fn test_clock_stretching() {
+        let bus = Bus::new(0x48, 3);
+        let mut read = [0];
+        i2c(&bus).read(0x48, &mut read).unwrap();
+        assert_eq!(read, [0xa0]);
+
+        let bus = Bus::new(0x48, usize::MAX);
+        let err = i2c(&bus).write(0x48, &[1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Bus);
+    }
+
+    #[test]
+    fn test_half_period() {
+        assert_eq!(half_period(100_000), Duration::from_micros(5));
+        assert_eq!(half_period(0), Duration::from_millis(500));
+        assert_eq!(frequency(Duration::from_micros(5)), 100_000);
+        assert_eq!(frequency(Duration::ZERO), 500_000_000);
+    }
+
+    /// Pin whose every access takes the given time
+    struct SlowPin(Duration);
+
+    impl embedded_hal::digital::ErrorType for SlowPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for SlowPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            std::thread::sleep(self.0);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            std::thread::sleep(self.0);
+            Ok(())
+        }
+    }
+
+    impl InputPin for SlowPin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            std::thread::sleep(self.0);
+            Ok(true)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            std::thread::sleep(self.0);
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn test_set_frequency() {
+        let pin = || SlowPin(Duration::ZERO);
+        let mut i2c = BitBangI2c::new(pin(), pin());
+        let hz = i2c.set_frequency(100).unwrap();
+        assert!((50..=100).contains(&hz), "{} Hz", hz);
+        assert!(i2c.half_period > Duration::from_millis(4));
+
+        // Pin accesses alone take longer than half a period
+        let pin = || SlowPin(Duration::from_micros(100));
+        let mut i2c = BitBangI2c::new(pin(), pin());
+        let hz = i2c.set_frequency(400_000).unwrap();
+        assert!(hz < 2_500, "{} Hz", hz);
+        assert_eq!(i2c.half_period, Duration::ZERO);
+    }
+}
@@ -0,0 +1,83 @@
+//! Implementation of [`embedded-hal`] digital input and output traits on a single open-drain
+//! Linux CDev line
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use embedded_hal::digital::PinState;
+
+use crate::CdevPinError;
+
+/// Newtype around [`gpio_cdev::LineHandle`] for a line that is both driven and read
+///
+/// The line is requested once as an open-drain output: driving it low pulls the wire low,
+/// while driving it high releases it to its pull-up, and reading it then returns the level
+/// of the wire, as set by the other devices on it. This suits single-wire bidirectional
+/// protocols such as 1-Wire, or bit-banged buses such as [`BitBangI2c`], which switch
+/// between sending and receiving often: no request has to be made to change direction, as
+/// with [`CdevPin::into_input_pin`] and [`CdevPin::into_output_pin`].
+///
+/// Reading the wire relies on the GPIO driver reporting the actual level of open-drain
+/// outputs, which most do; some only return the last value set.
+///
+/// [`gpio_cdev::LineHandle`]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.LineHandle.html
+/// [`BitBangI2c`]: crate::BitBangI2c
+/// [`CdevPin::into_input_pin`]: crate::CdevPin::into_input_pin
+/// [`CdevPin::into_output_pin`]: crate::CdevPin::into_output_pin
+pub struct CdevBidirPin(pub gpio_cdev::LineHandle);
+
+impl CdevBidirPin {
+    /// Request `line` as an open-drain output, released.
+    pub fn new(line: &gpio_cdev::Line, consumer: &str) -> Result<Self, CdevPinError> {
+        let flags = gpio_cdev::LineRequestFlags::OUTPUT | gpio_cdev::LineRequestFlags::OPEN_DRAIN;
+        Ok(CdevBidirPin(line.request(flags, 1, consumer)?))
+    }
+
+    /// Pull the wire low, or release it with [`PinState::High`].
+    pub fn set_value(&mut self, state: PinState) -> Result<(), CdevPinError> {
+        self.0.set_value(u8::from(bool::from(state)))?;
+        Ok(())
+    }
+
+    /// Read the level of the wire.
+    pub fn read_value(&self) -> Result<PinState, CdevPinError> {
+        Ok(PinState::from(self.0.get_value()? != 0))
+    }
+}
+
+impl embedded_hal::digital::ErrorType for CdevBidirPin {
+    type Error = CdevPinError;
+}
+
+impl embedded_hal::digital::OutputPin for CdevBidirPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_value(PinState::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_value(PinState::High)
+    }
+}
+
+impl embedded_hal::digital::InputPin for CdevBidirPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_value()? == PinState::High)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_value()? == PinState::Low)
+    }
+}
+
+impl core::ops::Deref for CdevBidirPin {
+    type Target = gpio_cdev::LineHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl core::ops::DerefMut for CdevBidirPin {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
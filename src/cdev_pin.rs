@@ -3,9 +3,33 @@
 //! [`embedded-hal`]: https://docs.rs/embedded-hal
 
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// Newtype around [`gpio_cdev::LineHandle`] that implements the `embedded-hal` traits
 ///
+/// # Bias
+///
+/// The GPIO character device ABI has bias flags since Linux 5.5, but the request flags of
+/// [`gpio_cdev`] 0.6 don't expose them, so neither the lines requested through it nor those
+/// requested again by this type, e.g. when changing modes, ever have their bias changed: it
+/// is always left "as-is", with whatever pull-up or pull-down the device tree, firmware or
+/// bootloader configured. This differs from requesting the bias to be "disabled", which
+/// actively removes any pull resistor and can't be requested through [`gpio_cdev`] 0.6.
+///
+/// # Edge detection
+///
+/// Edge detection, as used by [`CdevPin::wait_for_edge`] and, with the `async-tokio`
+/// feature, by `CdevPin::wait_once`, `CdevPin::measure_frequency`, `CdevDebouncedInput` and
+/// `CdevInputGroup`, requests the line for edge events, which the kernel only records from
+/// then on. Requesting the line can itself produce events, e.g. when the line glitches while
+/// switching from output to input, or when an interrupt latched earlier is delivered as the
+/// interrupt is enabled. So that a wait doesn't return right away for such an edge, the
+/// events already queued when the request returns are discarded. Edges are therefore
+/// reported if and only if they occur after the request, save for genuine edges occurring
+/// within the few microseconds it takes to discard the queue, which are lost. The level is
+/// not checked: waiting for a rising edge on a line that is already high waits for the next
+/// one.
+///
 /// [`gpio_cdev::LineHandle`]: https://docs.rs/gpio-cdev/0.5.0/gpio_cdev/struct.LineHandle.html
 pub struct CdevPin(pub gpio_cdev::LineHandle, gpio_cdev::LineInfo);
 
@@ -18,6 +42,55 @@ impl CdevPin {
         Ok(CdevPin(handle, info))
     }
 
+    /// Request `line` as an output, driving the level it is currently at rather than a fixed
+    /// initial value.
+    ///
+    /// The GPIO character device ABI used here always drives an initial value when requesting
+    /// an output, so the line is first requested "as-is", without changing its direction,
+    /// to read its current level, and then requested again as an output driving that level.
+    /// This avoids glitching reset or enable lines that are already driven, e.g. by the
+    /// bootloader. Set the desired level explicitly afterwards.
+    ///
+    /// If the line is currently an input, the level read, and thus driven, is that of the
+    /// input. The line is briefly released between both requests, during which another
+    /// process could request it.
+    pub fn new_output_preserve(
+        line: &gpio_cdev::Line,
+        consumer: &str,
+    ) -> Result<Self, CdevPinError> {
+        let value = {
+            let handle = line.request(gpio_cdev::LineRequestFlags::empty(), 0, consumer)?;
+            handle.get_value()?
+        };
+        let handle = line.request(gpio_cdev::LineRequestFlags::OUTPUT, value, consumer)?;
+        Ok(CdevPin::new(handle)?)
+    }
+
+    /// Query the configuration the kernel actually granted for this line.
+    ///
+    /// Unlike the information cached when this pin was created, this is read from the chip
+    /// on every call. It reports the direction, active-low, open-drain/open-source drive and
+    /// consumer of the line. The GPIO character device ABI used here does not report bias.
+    ///
+    /// See [`gpio_cdev::Line::info`][0] for details.
+    ///
+    /// [0]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.Line.html#method.info
+    pub fn line_info(&self) -> Result<gpio_cdev::LineInfo, CdevPinError> {
+        self.0.line().info().map_err(CdevPinError::from)
+    }
+
+    /// Whether `line` is free to be requested, i.e. not used by another process or by the
+    /// kernel.
+    ///
+    /// Requesting a line in use fails with a bare `EBUSY`; checking first allows reporting
+    /// who holds it, from the [`consumer`][0] of its info. The answer may be outdated by the
+    /// time the line is requested, as another process can request or release it meanwhile.
+    ///
+    /// [0]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.LineInfo.html#method.consumer
+    pub fn is_line_available(line: &gpio_cdev::Line) -> Result<bool, CdevPinError> {
+        Ok(!line.info()?.is_used())
+    }
+
     fn get_input_flags(&self) -> gpio_cdev::LineRequestFlags {
         if self.1.is_active_low() {
             return gpio_cdev::LineRequestFlags::INPUT | gpio_cdev::LineRequestFlags::ACTIVE_LOW;
@@ -39,48 +112,468 @@ impl CdevPin {
     }
 
     /// Set this pin to input mode
-    pub fn into_input_pin(self) -> Result<CdevPin, gpio_cdev::errors::Error> {
+    ///
+    /// The line has to be released and requested again to change its mode. If requesting it
+    /// in the new mode fails, it is requested again in its previous mode and returned
+    /// alongside the error, see [`ModeChangeError`].
+    pub fn into_input_pin(self) -> Result<CdevPin, ModeChangeError> {
         if self.1.direction() == gpio_cdev::LineDirection::In {
             return Ok(self);
         }
-        let line = self.0.line().clone();
         let input_flags = self.get_input_flags();
-        let consumer = self.1.consumer().unwrap_or("").to_owned();
-
-        // Drop self to free the line before re-requesting it in a new mode.
-        std::mem::drop(self);
-
-        CdevPin::new(line.request(input_flags, 0, &consumer)?)
+        self.rerequest(input_flags, 0)
     }
 
     /// Set this pin to output mode
+    ///
+    /// The line has to be released and requested again to change its mode. If requesting it
+    /// in the new mode fails, it is requested again in its previous mode and returned
+    /// alongside the error, see [`ModeChangeError`].
     pub fn into_output_pin(
         self,
         state: embedded_hal::digital::PinState,
-    ) -> Result<CdevPin, gpio_cdev::errors::Error> {
+    ) -> Result<CdevPin, ModeChangeError> {
         if self.1.direction() == gpio_cdev::LineDirection::Out {
             return Ok(self);
         }
+        let output_flags = self.get_output_flags();
+        let is_active_low = output_flags.intersects(gpio_cdev::LineRequestFlags::ACTIVE_LOW);
+        self.rerequest(output_flags, state_to_value(state, is_active_low))
+    }
 
+    /// Block until an edge selected by `edges` occurs on the line, and return it along with
+    /// the pin.
+    ///
+    /// This is the blocking counterpart of the edge detection available with the
+    /// `async-tokio` feature, for programs without an async runtime. The line is released
+    /// and requested for edge events for the duration of the wait, so edges occurring before
+    /// this call, or between two calls, are not reported, nor are events produced by
+    /// requesting the line, see [edge detection](CdevPin#edge-detection). The pin is handed
+    /// back as an input, with its active-low setting preserved.
+    ///
+    /// If the wait fails, the pin is requested again as an input and returned alongside the
+    /// error, see [`ModeChangeError`].
+    ///
+    /// See [`gpio_cdev::Line::events`][0] for details.
+    ///
+    /// [0]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.Line.html#method.events
+    pub fn wait_for_edge(
+        self,
+        edges: gpio_cdev::EventRequestFlags,
+    ) -> Result<(CdevPin, gpio_cdev::LineEvent), ModeChangeError> {
+        let line = self.0.line().clone();
+        let consumer = self.1.consumer().unwrap_or("").to_owned();
+        let input_flags = self.get_input_flags();
+
+        // Drop self to free the line before requesting it for events.
+        std::mem::drop(self);
+
+        let event = line
+            .events(input_flags.clone(), edges, &consumer)
+            .and_then(|mut events| {
+                discard_pending_events(&events)?;
+                events.get_event()
+            });
+        let pin = line
+            .request(input_flags, 0, &consumer)
+            .and_then(CdevPin::new);
+        match (event, pin) {
+            (Ok(event), Ok(pin)) => Ok((pin, event)),
+            (Err(err), pin) => Err(ModeChangeError {
+                err: err.into(),
+                pin: pin.ok(),
+            }),
+            (Ok(_), Err(err)) => Err(ModeChangeError {
+                err: err.into(),
+                pin: None,
+            }),
+        }
+    }
+
+    /// Drive the line to `state` for `duration`, then to the opposite state.
+    ///
+    /// This produces pulses such as a reset or a trigger signal. The wait uses [`Delay`],
+    /// which sleeps for long pulses and, once [calibrated], finishes short ones by spinning.
+    /// Linux is not a realtime kernel, so the pulse is at least `duration` long but may be
+    /// stretched by scheduling: usually by some tens of microseconds, by milliseconds or more
+    /// on a loaded system, as the thread can be preempted while the line is in `state`. Each
+    /// edge also takes a system call, adding a few microseconds. Pulses that must be exact
+    /// need a PWM or a hardware timer.
+    ///
+    /// If the line cannot be driven to `state`, it is left as it was. If driving it back
+    /// fails, the error is returned and the line is left in `state`.
+    ///
+    /// [`Delay`]: crate::Delay
+    /// [calibrated]: crate::Delay::calibrate
+    pub fn pulse(
+        &mut self,
+        state: embedded_hal::digital::PinState,
+        duration: std::time::Duration,
+    ) -> Result<(), CdevPinError> {
+        use embedded_hal::digital::OutputPin;
+
+        self.set_state(state)?;
+        crate::Delay::sleep(duration);
+        self.set_state(!state)
+    }
+
+    /// Release the line, consuming the pin, and return the line so that it can be requested
+    /// again.
+    ///
+    /// Dropping the pin releases the line as well; this makes the point where it happens
+    /// explicit, e.g. to hand the line over to another consumer in the middle of a scope. The
+    /// line is free for other requests, including from other processes, once this returns.
+    /// Closing the request cannot fail, so there is no error to report.
+    pub fn release(self) -> gpio_cdev::Line {
+        let line = self.0.line().clone();
+        std::mem::drop(self);
+        line
+    }
+
+    /// Release the line and request it again with `flags` and `value`, restoring the
+    /// previous request if that fails.
+    fn rerequest(
+        self,
+        flags: gpio_cdev::LineRequestFlags,
+        value: u8,
+    ) -> Result<CdevPin, ModeChangeError> {
         let line = self.0.line().clone();
-        let output_flags = self.get_output_flags();
         let consumer = self.1.consumer().unwrap_or("").to_owned();
+        let old_flags = self.0.flags();
+        let old_value = self.0.get_value().unwrap_or(0);
 
         // Drop self to free the line before re-requesting it in a new mode.
         std::mem::drop(self);
 
-        let is_active_low = output_flags.intersects(gpio_cdev::LineRequestFlags::ACTIVE_LOW);
-        CdevPin::new(line.request(
-            output_flags,
-            state_to_value(state, is_active_low),
-            &consumer,
-        )?)
+        with_restore(
+            || CdevPin::new(line.request(flags, value, &consumer)?),
+            || CdevPin::new(line.request(old_flags, old_value, &consumer)?),
+        )
+        .map_err(|(err, pin)| ModeChangeError {
+            err: err.into(),
+            pin,
+        })
+    }
+}
+
+/// Run `request`, and if it fails, `restore`, returning its result alongside the error.
+fn with_restore<T, E>(
+    request: impl FnOnce() -> Result<T, E>,
+    restore: impl FnOnce() -> Result<T, E>,
+) -> Result<T, (E, Option<T>)> {
+    request().map_err(|err| (err, restore().ok()))
+}
+
+/// Error returned when changing the mode of a [`CdevPin`], or waiting for an edge on it, fails
+///
+/// Unless the line could not be requested again either, the pin is handed back, so it can
+/// still be used or the operation retried.
+pub struct ModeChangeError {
+    err: CdevPinError,
+    pin: Option<CdevPin>,
+}
+
+impl ModeChangeError {
+    /// Fetch the error that made the mode change fail
+    pub fn error(&self) -> &CdevPinError {
+        &self.err
+    }
+
+    /// Take back the pin in its previous mode, if it could be restored
+    pub fn into_pin(self) -> Option<CdevPin> {
+        self.pin
+    }
+}
+
+impl fmt::Debug for ModeChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModeChangeError")
+            .field("err", &self.err)
+            .field("restored", &self.pin.is_some())
+            .finish()
+    }
+}
+
+impl fmt::Display for ModeChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.err)?;
+        if self.pin.is_none() {
+            write!(f, " (line released)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ModeChangeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.err)
+    }
+}
+
+impl From<ModeChangeError> for CdevPinError {
+    fn from(err: ModeChangeError) -> Self {
+        err.err
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl CdevPin {
+    /// Drive the line through a schedule of `(delay, state)` steps: for each step, wait for
+    /// `delay` after the previous step, then set the line to `state`.
+    ///
+    /// Each step is scheduled from the deadline of the previous one rather than from when
+    /// it was actually performed, so lateness does not accumulate over the schedule, and a
+    /// late step is followed by shorter waits to catch up. Timing still depends on the
+    /// scheduler: tokio timers have a resolution of one millisecond, and each transition may
+    /// additionally be delayed by the wakeup latency of the task, typically tens to hundreds
+    /// of microseconds. This suits waveforms at low to moderate rates, such as slow stepper
+    /// pulse trains, not precise high-frequency signals.
+    pub async fn play_schedule(
+        &mut self,
+        steps: &[(std::time::Duration, embedded_hal::digital::PinState)],
+    ) -> Result<(), CdevPinError> {
+        use embedded_hal::digital::OutputPin;
+
+        let mut deadline = tokio::time::Instant::now();
+        for &(delay, state) in steps {
+            deadline += delay;
+            tokio::time::sleep_until(deadline).await;
+            self.set_state(state)?;
+        }
+        Ok(())
+    }
+
+    /// Measure the frequency of the signal on `line`, in Hz, by counting its rising edges
+    /// during a `gate` window.
+    ///
+    /// The line is requested for edge events for the duration of the measurement and released
+    /// afterwards, so it must not be requested elsewhere, including by a [`CdevPin`].
+    ///
+    /// The frequency is computed from the kernel timestamps of the first and last edge seen
+    /// during the window, rather than from when this function started and stopped waiting,
+    /// so scheduling latency of this task doesn't affect the result. Timestamps are taken by
+    /// the kernel in the interrupt handler, which leaves the interrupt latency jitter of the
+    /// system, typically some microseconds. Short gates on slow signals see few edges and give
+    /// coarse results.
+    ///
+    /// If no edge occurs during the window, `0.0` is returned. With a single edge, no period
+    /// can be measured, and the result is one edge per `gate`.
+    pub async fn measure_frequency(
+        line: &gpio_cdev::Line,
+        consumer: &str,
+        gate: std::time::Duration,
+    ) -> Result<f64, CdevPinError> {
+        use futures_core::Stream;
+
+        let mut events = line.async_events(
+            gpio_cdev::LineRequestFlags::INPUT,
+            gpio_cdev::EventRequestFlags::RISING_EDGE,
+            consumer,
+        )?;
+        discard_pending_events(events.as_ref())?;
+        let deadline = tokio::time::Instant::now() + gate;
+        let mut count = 0;
+        let mut first = 0;
+        let mut last = 0;
+        loop {
+            let next = std::future::poll_fn(|cx| std::pin::Pin::new(&mut events).poll_next(cx));
+            match tokio::time::timeout_at(deadline, next).await {
+                Ok(Some(event)) => {
+                    let timestamp = event?.timestamp();
+                    if count == 0 {
+                        first = timestamp;
+                    }
+                    last = timestamp;
+                    count += 1;
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        Ok(edges_to_frequency(count, last - first, gate))
+    }
+
+    /// Wait for a single edge selected by `edges` on `line`, for at most `timeout`, and return
+    /// whether one occurred.
+    ///
+    /// The line is requested for edge events only for the duration of the wait, and released
+    /// before returning, so it must not be requested elsewhere, including by a [`CdevPin`].
+    /// This suits lines only watched once, such as a ready line signalling the end of a
+    /// device's initialization. Edges occurring before the call are not reported, nor are
+    /// events produced by requesting the line, see [edge detection](CdevPin#edge-detection).
+    pub async fn wait_once(
+        line: &gpio_cdev::Line,
+        edges: gpio_cdev::EventRequestFlags,
+        consumer: &str,
+        timeout: std::time::Duration,
+    ) -> Result<bool, CdevPinError> {
+        use futures_core::Stream;
+
+        let mut events = line.async_events(gpio_cdev::LineRequestFlags::INPUT, edges, consumer)?;
+        discard_pending_events(events.as_ref())?;
+        let next = std::future::poll_fn(|cx| std::pin::Pin::new(&mut events).poll_next(cx));
+        match tokio::time::timeout(timeout, next).await {
+            Ok(Some(event)) => event.map(|_| true).map_err(CdevPinError::from),
+            Ok(None) | Err(_) => Ok(false),
+        }
+    }
+}
+
+/// Edge events of a single line, debounced in software
+///
+/// For lines whose GPIO chip has no hardware debounce, such as lines wired to mechanical
+/// switches: once an edge is reported, every further edge, of either kind, is ignored for a
+/// `guard` time, so that a bouncing contact gives a single rising or falling edge per
+/// transition. `guard` must therefore be shorter than the shortest press to be seen, as the
+/// release edge of a shorter one is ignored too.
+///
+/// The guard time is measured with the kernel timestamps of the edges, from the edge that was
+/// reported, rather than from when this task got to see it, so scheduling latency neither
+/// shortens nor extends it, and it doesn't drift over many edges. Bounces lasting longer than
+/// `guard` give additional edges.
+///
+/// The line is requested for edge events for as long as this exists, so it must not be
+/// requested elsewhere, including by a [`CdevPin`].
+#[cfg(feature = "async-tokio")]
+pub struct CdevDebouncedInput {
+    events: gpio_cdev::AsyncLineEventHandle,
+    debouncer: Debouncer,
+}
+
+#[cfg(feature = "async-tokio")]
+impl CdevDebouncedInput {
+    /// Request `line` for the edge events selected by `edges`, ignoring those following a
+    /// reported edge within `guard`.
+    pub fn new(
+        line: &gpio_cdev::Line,
+        edges: gpio_cdev::EventRequestFlags,
+        consumer: &str,
+        guard: std::time::Duration,
+    ) -> Result<Self, CdevPinError> {
+        let events = line.async_events(gpio_cdev::LineRequestFlags::INPUT, edges, consumer)?;
+        discard_pending_events(events.as_ref())?;
+        Ok(CdevDebouncedInput {
+            events,
+            debouncer: Debouncer::new(guard),
+        })
+    }
+
+    /// Wait for the next edge event that isn't a bounce of a previous one.
+    pub async fn next_event(&mut self) -> Result<gpio_cdev::LineEvent, CdevPinError> {
+        use futures_core::Stream;
+
+        loop {
+            let events = &mut self.events;
+            let next = std::future::poll_fn(|cx| std::pin::Pin::new(&mut *events).poll_next(cx));
+            // The stream of a line handle never ends
+            if let Some(event) = next.await {
+                let event = event?;
+                if self.debouncer.accept(event.timestamp()) {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+
+    /// Wait for the next rising edge that isn't a bounce of a previous one.
+    ///
+    /// Falling edges are discarded, so this is only useful with rising edges requested.
+    pub async fn wait_for_rising_edge(&mut self) -> Result<(), CdevPinError> {
+        self.wait_for(gpio_cdev::EventType::RisingEdge).await
+    }
+
+    /// Wait for the next falling edge that isn't a bounce of a previous one.
+    ///
+    /// Rising edges are discarded, so this is only useful with falling edges requested.
+    pub async fn wait_for_falling_edge(&mut self) -> Result<(), CdevPinError> {
+        self.wait_for(gpio_cdev::EventType::FallingEdge).await
+    }
+
+    async fn wait_for(&mut self, kind: gpio_cdev::EventType) -> Result<(), CdevPinError> {
+        while self.next_event().await?.event_type() != kind {}
+        Ok(())
+    }
+}
+
+/// Tracks the timestamp of the last reported edge, in nanoseconds
+#[cfg(feature = "async-tokio")]
+struct Debouncer {
+    guard_ns: u64,
+    last: Option<u64>,
+}
+
+#[cfg(feature = "async-tokio")]
+impl Debouncer {
+    fn new(guard: std::time::Duration) -> Self {
+        use std::convert::TryFrom;
+
+        Debouncer {
+            guard_ns: u64::try_from(guard.as_nanos()).unwrap_or(u64::MAX),
+            last: None,
+        }
+    }
+
+    /// Whether an edge at `timestamp` is to be reported, recording it if so
+    fn accept(&mut self, timestamp: u64) -> bool {
+        match self.last {
+            Some(previous) if timestamp.saturating_sub(previous) < self.guard_ns => false,
+            _ => {
+                self.last = Some(timestamp);
+                true
+            }
+        }
     }
 }
 
+/// Computes a frequency in Hz from `count` edges, the first and last of which are `span_ns`
+/// nanoseconds apart, seen during a `gate` window.
+#[cfg(feature = "async-tokio")]
+fn edges_to_frequency(count: u64, span_ns: u64, gate: std::time::Duration) -> f64 {
+    match count {
+        0 => 0.0,
+        1 => 1.0 / gate.as_secs_f64(),
+        _ if span_ns == 0 => count as f64 / gate.as_secs_f64(),
+        _ => (count - 1) as f64 * 1e9 / span_ns as f64,
+    }
+}
+
+/// Discard the edge events already queued on `handle`, see
+/// [edge detection](CdevPin#edge-detection).
+pub(crate) fn discard_pending_events(
+    handle: &gpio_cdev::LineEventHandle,
+) -> Result<(), gpio_cdev::errors::Error> {
+    discard_pending(handle.file())?;
+    Ok(())
+}
+
+/// Read and drop whatever can be read from `file` without blocking, and return the number of
+/// bytes dropped. The status flags of `file` are left as they were.
+fn discard_pending(mut file: &std::fs::File) -> std::io::Result<usize> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+    fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+    // Events are 16 bytes, and the kernel returns as many whole events as fit
+    let mut buf = [0; 256];
+    let mut discarded = 0;
+    let res = loop {
+        match file.read(&mut buf) {
+            Ok(0) => break Ok(discarded),
+            Ok(n) => discarded += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(discarded),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    fcntl(fd, FcntlArg::F_SETFL(flags))?;
+    res
+}
+
 /// Converts a pin state to the gpio_cdev compatible numeric value, accounting
 /// for the active_low condition.
-fn state_to_value(state: embedded_hal::digital::PinState, is_active_low: bool) -> u8 {
+pub(crate) fn state_to_value(state: embedded_hal::digital::PinState, is_active_low: bool) -> u8 {
     if is_active_low {
         match state {
             embedded_hal::digital::PinState::High => 0,
@@ -138,20 +631,16 @@ impl embedded_hal::digital::ErrorType for CdevPin {
 
 impl embedded_hal::digital::OutputPin for CdevPin {
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.0
-            .set_value(state_to_value(
-                embedded_hal::digital::PinState::Low,
-                self.1.is_active_low(),
-            ))
-            .map_err(CdevPinError::from)
+        self.set_state(embedded_hal::digital::PinState::Low)
     }
 
     fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(embedded_hal::digital::PinState::High)
+    }
+
+    fn set_state(&mut self, state: embedded_hal::digital::PinState) -> Result<(), Self::Error> {
         self.0
-            .set_value(state_to_value(
-                embedded_hal::digital::PinState::High,
-                self.1.is_active_low(),
-            ))
+            .set_value(state_to_value(state, self.1.is_active_low()))
             .map_err(CdevPinError::from)
     }
 }
@@ -174,6 +663,25 @@ impl embedded_hal::digital::InputPin for CdevPin {
     }
 }
 
+/// Pins are identified by the path of their chip and their offset on it, not by file
+/// descriptor.
+impl PartialEq for CdevPin {
+    fn eq(&self, other: &Self) -> bool {
+        let (line, other) = (self.0.line(), other.0.line());
+        line.chip().path() == other.chip().path() && line.offset() == other.offset()
+    }
+}
+
+impl Eq for CdevPin {}
+
+impl Hash for CdevPin {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let line = self.0.line();
+        line.chip().path().hash(state);
+        line.offset().hash(state);
+    }
+}
+
 impl core::ops::Deref for CdevPin {
     type Target = gpio_cdev::LineHandle;
 
@@ -187,3 +695,87 @@ impl core::ops::DerefMut for CdevPin {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_restore() {
+        let res: Result<u8, (&str, _)> = with_restore(|| Ok(1), || panic!("restored on success"));
+        assert_eq!(res, Ok(1));
+
+        // A failed reconfiguration hands back the restored pin
+        let res: Result<u8, _> = with_restore(|| Err("busy"), || Ok(0));
+        assert_eq!(res, Err(("busy", Some(0))));
+
+        let res: Result<u8, _> = with_restore(|| Err("busy"), || Err("gone"));
+        assert_eq!(res, Err(("busy", None)));
+    }
+
+    #[test]
+    fn test_discard_pending() {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+        use std::io::Write;
+        use std::os::unix::io::{AsRawFd, OwnedFd};
+        use std::os::unix::net::UnixStream;
+
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        let rx = std::fs::File::from(OwnedFd::from(rx));
+        assert_eq!(discard_pending(&rx).unwrap(), 0);
+
+        tx.write_all(&[0; 3 * 16]).unwrap();
+        assert_eq!(discard_pending(&rx).unwrap(), 3 * 16);
+        assert_eq!(discard_pending(&rx).unwrap(), 0);
+
+        // Still blocking afterwards
+        let flags = OFlag::from_bits_truncate(fcntl(rx.as_raw_fd(), FcntlArg::F_GETFL).unwrap());
+        assert!(!flags.contains(OFlag::O_NONBLOCK));
+    }
+
+    #[cfg(feature = "async-tokio")]
+    #[test]
+    fn test_debouncer() {
+        const MS: u64 = 1_000_000;
+
+        let mut debouncer = Debouncer::new(std::time::Duration::from_millis(10));
+        // A press bouncing for 3ms, released 50ms later with 2ms of bounces
+        let edges = [
+            (true, 0),
+            (false, MS / 2),
+            (true, MS),
+            (false, 2 * MS),
+            (true, 3 * MS),
+            (false, 50 * MS),
+            (true, 51 * MS),
+            (false, 52 * MS),
+        ];
+        let accepted: Vec<_> = edges
+            .iter()
+            .filter(|&&(_, timestamp)| debouncer.accept(timestamp))
+            .collect();
+        // One rising edge for the press, one falling edge for the release
+        assert_eq!(accepted, [&(true, 0), &(false, 50 * MS)]);
+
+        // The guard runs from the reported edge, not from the ignored ones
+        let mut debouncer = Debouncer::new(std::time::Duration::from_millis(10));
+        let accepted: Vec<_> = (0..25)
+            .map(|i| i * MS)
+            .filter(|&timestamp| debouncer.accept(timestamp))
+            .collect();
+        assert_eq!(accepted, [0, 10 * MS, 20 * MS]);
+    }
+
+    #[cfg(feature = "async-tokio")]
+    #[test]
+    fn test_edges_to_frequency() {
+        use std::time::Duration;
+
+        let gate = Duration::from_secs(1);
+        assert_eq!(edges_to_frequency(0, 0, gate), 0.0);
+        assert_eq!(edges_to_frequency(1, 0, gate), 1.0);
+        // 11 edges 10ms apart span 100ms: 100 Hz
+        assert!((edges_to_frequency(11, 100_000_000, gate) - 100.0).abs() < 1e-9);
+        assert!((edges_to_frequency(3, 1_000, gate) - 2_000_000.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,287 @@
+//! Implementation of [`embedded-hal`] digital input and output traits for several lines of a
+//! Linux CDev chip accessed together
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use embedded_hal::digital::PinState;
+
+use crate::cdev_pin::state_to_value;
+use crate::CdevPinError;
+
+/// Newtype around [`gpio_cdev::MultiLineHandle`] driving `N` output lines at once
+///
+/// All lines are requested in a single kernel call, with their initial values set as part
+/// of that request. This avoids the brief wrong-state pulse that requesting the lines first
+/// and writing them afterwards would cause on enable or reset lines.
+///
+/// [`gpio_cdev::MultiLineHandle`]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.MultiLineHandle.html
+pub struct CdevOutputPort<const N: usize>(pub gpio_cdev::MultiLineHandle);
+
+impl<const N: usize> CdevOutputPort<N> {
+    /// Request the lines at `offsets` of `chip` as outputs.
+    ///
+    /// Line `offsets[i]` is initially driven to `states[i]`.
+    ///
+    /// See [`gpio_cdev::Lines::request`][0] for details.
+    ///
+    /// [0]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.Lines.html#method.request
+    pub fn new(
+        chip: &mut gpio_cdev::Chip,
+        offsets: [u32; N],
+        states: [PinState; N],
+        consumer: &str,
+    ) -> Result<Self, CdevPinError> {
+        let lines = chip.get_lines(&offsets)?;
+        let values = line_values(states);
+        let handle = lines.request(gpio_cdev::LineRequestFlags::OUTPUT, &values, consumer)?;
+        Ok(CdevOutputPort(handle))
+    }
+
+    /// Drive every line of the port in a single kernel call.
+    ///
+    /// Line `i` of the port is set to `states[i]`.
+    pub fn set_states(&mut self, states: [PinState; N]) -> Result<(), CdevPinError> {
+        let values = line_values(states);
+        self.0.set_values(&values).map_err(CdevPinError::from)
+    }
+}
+
+/// Line values of the port lines driven to `states`, the value at index `i` giving line `i`.
+fn line_values<const N: usize>(states: [PinState; N]) -> [u8; N] {
+    states.map(|state| state_to_value(state, false))
+}
+
+impl<const N: usize> core::ops::Deref for CdevOutputPort<N> {
+    type Target = gpio_cdev::MultiLineHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const N: usize> core::ops::DerefMut for CdevOutputPort<N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Newtype around [`gpio_cdev::MultiLineHandle`] reading `N` input lines at once
+///
+/// All lines are read in a single kernel call, so the values are sampled simultaneously,
+/// without the skew that reading the lines one after the other would introduce. This suits
+/// parallel inputs such as keypad columns or DIP switches. The kernel accepts up to 64 lines
+/// per request, so `N` may be at most 64.
+///
+/// [`gpio_cdev::MultiLineHandle`]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.MultiLineHandle.html
+pub struct CdevInputPort<const N: usize>(pub gpio_cdev::MultiLineHandle);
+
+impl<const N: usize> CdevInputPort<N> {
+    const MAX_LINES_CHECK: () = assert!(N <= 64, "at most 64 lines can be requested at once");
+
+    /// Request the lines at `offsets` of `chip` as inputs.
+    ///
+    /// See [`gpio_cdev::Lines::request`][0] for details.
+    ///
+    /// [0]: https://docs.rs/gpio-cdev/0.6.0/gpio_cdev/struct.Lines.html#method.request
+    pub fn new(
+        chip: &mut gpio_cdev::Chip,
+        offsets: [u32; N],
+        consumer: &str,
+    ) -> Result<Self, CdevPinError> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::MAX_LINES_CHECK;
+        let lines = chip.get_lines(&offsets)?;
+        let handle = lines.request(gpio_cdev::LineRequestFlags::INPUT, &[0; N], consumer)?;
+        Ok(CdevInputPort(handle))
+    }
+
+    /// Read every line of the port in a single kernel call.
+    ///
+    /// Bit `i` of the result is set if line `i` of the port, that is `offsets[i]` as passed
+    /// to [`CdevInputPort::new`], is high. Bits `N` and above are clear.
+    pub fn read_values(&mut self) -> Result<u64, CdevPinError> {
+        let values = self.0.get_values()?;
+        Ok(pack_values(&values))
+    }
+}
+
+/// Pack line values into a bitmask, the value at index `i` giving bit `i`.
+fn pack_values(values: &[u8]) -> u64 {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, &value)| value != 0)
+        .fold(0, |mask, (i, _)| mask | 1 << i)
+}
+
+impl<const N: usize> core::ops::Deref for CdevInputPort<N> {
+    type Target = gpio_cdev::MultiLineHandle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const N: usize> core::ops::DerefMut for CdevInputPort<N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Edge events of several input lines, awaited together from a single task
+///
+/// Each line is requested for the edges given to [`CdevInputGroup::new`], and
+/// [`CdevInputGroup::next_event`] resolves with the first event of any of them, along with the
+/// offset of the line it came from. This avoids spawning one task per line when watching a
+/// bank of interrupt lines, such as those of GPIO expanders.
+///
+/// The v1 character device ABI used by `gpio-cdev` requests edge events one line at a time,
+/// so each line is a separate request with its own file descriptor, all of them polled by
+/// the same task. Lines are polled in turn, starting after the line that produced the
+/// previous event, so that a line with frequent edges cannot starve the others. Events of
+/// a single line are reported in order, but events of different lines are not ordered
+/// between each other; use [`gpio_cdev::LineEvent::timestamp`] to order them.
+#[cfg(feature = "async-tokio")]
+pub struct CdevInputGroup {
+    offsets: Vec<u32>,
+    handles: Vec<gpio_cdev::AsyncLineEventHandle>,
+    next: usize,
+}
+
+#[cfg(feature = "async-tokio")]
+impl CdevInputGroup {
+    /// Request the lines at `offsets` of `chip` as inputs reporting `edges`.
+    ///
+    /// Events produced by requesting the lines are discarded, see
+    /// [edge detection](crate::CdevPin#edge-detection).
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(
+        chip: &mut gpio_cdev::Chip,
+        offsets: &[u32],
+        edges: gpio_cdev::EventRequestFlags,
+        consumer: &str,
+    ) -> Result<Self, CdevPinError> {
+        let handles = offsets
+            .iter()
+            .map(|&offset| {
+                // `EventRequestFlags` is not `Clone`, so each request gets a copy of its bits
+                let edges = gpio_cdev::EventRequestFlags::from_bits_retain(edges.bits());
+                let handle = chip.get_line(offset)?.async_events(
+                    gpio_cdev::LineRequestFlags::INPUT,
+                    edges,
+                    consumer,
+                )?;
+                crate::cdev_pin::discard_pending_events(handle.as_ref())?;
+                Ok(handle)
+            })
+            .collect::<Result<_, gpio_cdev::errors::Error>>()?;
+        Ok(CdevInputGroup {
+            offsets: offsets.to_vec(),
+            handles,
+            next: 0,
+        })
+    }
+
+    /// The offsets of the lines of the group, in the order they were requested
+    pub fn offsets(&self) -> impl Iterator<Item = u32> + '_ {
+        self.offsets.iter().copied()
+    }
+
+    /// Wait for the next edge event on any line of the group, and return it along with the
+    /// offset of its line.
+    ///
+    /// With no lines in the group, this never resolves.
+    pub async fn next_event(&mut self) -> Result<(u32, gpio_cdev::LineEvent), CdevPinError> {
+        let (handles, next) = (&mut self.handles, &mut self.next);
+        let (index, event) = std::future::poll_fn(|cx| poll_fair(handles, next, cx)).await;
+        Ok((self.offsets[index], event?))
+    }
+}
+
+/// Poll `streams` in turn, starting at index `*next`, and return the index and item of the
+/// first ready stream, setting `*next` to the index following it.
+///
+/// Ended streams are skipped.
+#[cfg(feature = "async-tokio")]
+fn poll_fair<S: futures_core::Stream + Unpin>(
+    streams: &mut [S],
+    next: &mut usize,
+    cx: &mut std::task::Context<'_>,
+) -> std::task::Poll<(usize, S::Item)> {
+    use std::task::Poll;
+
+    let len = streams.len();
+    for i in (0..len).map(|i| (*next + i) % len) {
+        if let Poll::Ready(Some(item)) = std::pin::Pin::new(&mut streams[i]).poll_next(cx) {
+            *next = (i + 1) % len;
+            return Poll::Ready((i, item));
+        }
+    }
+    Poll::Pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_values() {
+        assert_eq!(line_values::<0>([]), []);
+        assert_eq!(
+            line_values([PinState::High, PinState::Low, PinState::Low, PinState::High]),
+            [1, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_pack_values() {
+        assert_eq!(pack_values(&[]), 0);
+        assert_eq!(pack_values(&[1, 0, 1, 1]), 0b1101);
+        let mut values = [0; 64];
+        values[63] = 1;
+        assert_eq!(pack_values(&values), 1 << 63);
+    }
+
+    #[cfg(feature = "async-tokio")]
+    #[tokio::test]
+    async fn test_poll_fair() {
+        use std::collections::VecDeque;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        /// Stream yielding its queued items, then staying pending
+        struct Queue(VecDeque<u8>);
+
+        impl futures_core::Stream for Queue {
+            type Item = u8;
+
+            fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<u8>> {
+                match self.0.pop_front() {
+                    Some(item) => Poll::Ready(Some(item)),
+                    None => Poll::Pending,
+                }
+            }
+        }
+
+        let mut streams = [
+            Queue(VecDeque::from([1, 2, 3])),
+            Queue(VecDeque::new()),
+            Queue(VecDeque::from([4])),
+        ];
+        let mut next = 0;
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            let event = std::future::poll_fn(|cx| poll_fair(&mut streams, &mut next, cx)).await;
+            events.push(event);
+        }
+        // The busy first stream doesn't hold back the last one
+        assert_eq!(events, [(0, 1), (2, 4), (0, 2), (0, 3)]);
+
+        let pending = std::future::poll_fn(|cx| match poll_fair(&mut streams, &mut next, cx) {
+            Poll::Pending => Poll::Ready(true),
+            Poll::Ready(_) => Poll::Ready(false),
+        });
+        assert!(pending.await);
+    }
+}
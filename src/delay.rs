@@ -3,20 +3,101 @@
 //! [`embedded-hal`]: https://docs.rs/embedded-hal
 
 use embedded_hal::delay::DelayNs;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Empty struct that provides delay functionality on top of `thread::sleep`,
 /// and `tokio::time::sleep` if the `async-tokio` feature is enabled.
+///
+/// Once [calibrated](Delay::calibrate), blocking delays below a millisecond compensate for
+/// the overshoot of `thread::sleep` by sleeping that much less and busy-waiting for the rest,
+/// see [`SpinDelay`].
 pub struct Delay;
 
+/// Measured overshoot of `thread::sleep`, in nanoseconds, or `u64::MAX` if not calibrated
+static SLEEP_OVERSHOOT_NS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Timing characteristics of the system measured by [`Delay::calibrate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelayCalibration {
+    /// How much longer than requested a short `thread::sleep` typically takes
+    ///
+    /// This is the granularity of sleeping delays: below it, busy-waiting is more accurate.
+    pub sleep_overshoot: Duration,
+}
+
+impl Delay {
+    /// Measure the timing of `thread::sleep` on this system, and use it to make later short
+    /// delays more accurate.
+    ///
+    /// Sleeping takes longer than requested by a system-dependent amount, from a few to
+    /// hundreds of microseconds depending on the CPU, its frequency scaling and the kernel
+    /// timer configuration. After calibration, delays below a millisecond sleep for the
+    /// requested time minus the measured overshoot, if any, and busy-wait until the deadline,
+    /// which brings them close to the requested duration at the cost of some CPU time. The
+    /// calibration is global: it applies to all [`Delay`] instances of the process, so from
+    /// then on, every short delay of every [`Delay`], including those given to drivers and
+    /// other libraries, busy-waits for its last part. Calibrating again replaces it.
+    ///
+    /// This takes a few milliseconds. Delays never end early, calibrated or not.
+    pub fn calibrate() -> DelayCalibration {
+        const SAMPLES: usize = 11;
+        const SLEEP: Duration = Duration::from_micros(10);
+
+        let mut overshoots: Vec<Duration> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                thread::sleep(SLEEP);
+                start.elapsed().saturating_sub(SLEEP)
+            })
+            .collect();
+        overshoots.sort();
+        let sleep_overshoot = overshoots[SAMPLES / 2];
+
+        SLEEP_OVERSHOOT_NS.store(as_nanos(sleep_overshoot), Ordering::Relaxed);
+        DelayCalibration { sleep_overshoot }
+    }
+
+    /// The calibration in use, if [`Delay::calibrate`] was called
+    pub fn calibration() -> Option<DelayCalibration> {
+        let sleep_overshoot = SLEEP_OVERSHOOT_NS.load(Ordering::Relaxed);
+        (sleep_overshoot != u64::MAX).then(|| DelayCalibration {
+            sleep_overshoot: Duration::from_nanos(sleep_overshoot),
+        })
+    }
+
+    /// Block for `duration`, compensating for the sleep overshoot once calibrated.
+    pub(crate) fn sleep(duration: Duration) {
+        let overshoot = SLEEP_OVERSHOOT_NS.load(Ordering::Relaxed);
+        if overshoot == u64::MAX || duration >= Duration::from_millis(1) {
+            thread::sleep(duration);
+            return;
+        }
+        let start = Instant::now();
+        let sleep = duration.saturating_sub(Duration::from_nanos(overshoot));
+        if !sleep.is_zero() {
+            thread::sleep(sleep);
+        }
+        while start.elapsed() < duration {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// `duration` in nanoseconds, saturating
+fn as_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX - 1)
+}
+
 impl DelayNs for Delay {
     fn delay_ns(&mut self, n: u32) {
-        thread::sleep(Duration::from_nanos(n.into()));
+        Delay::sleep(Duration::from_nanos(n.into()));
     }
 
     fn delay_us(&mut self, n: u32) {
-        thread::sleep(Duration::from_micros(n.into()));
+        Delay::sleep(Duration::from_micros(n.into()));
     }
 
     fn delay_ms(&mut self, n: u32) {
@@ -38,3 +119,165 @@ impl embedded_hal_async::delay::DelayNs for Delay {
         tokio::time::sleep(Duration::from_millis(n.into())).await;
     }
 }
+
+/// Empty struct that provides delays which return immediately.
+///
+/// This is meant for simulation, CI, or replaying captured bus traffic, where
+/// drivers should run without actually waiting. Only use it when nothing on the
+/// other end depends on the timing: real hardware will likely misbehave.
+pub struct NoopDelay;
+
+impl DelayNs for NoopDelay {
+    fn delay_ns(&mut self, _n: u32) {}
+
+    fn delay_us(&mut self, _n: u32) {}
+
+    fn delay_ms(&mut self, _n: u32) {}
+}
+
+#[cfg(feature = "async-tokio")]
+impl embedded_hal_async::delay::DelayNs for NoopDelay {
+    async fn delay_ns(&mut self, _n: u32) {}
+
+    async fn delay_us(&mut self, _n: u32) {}
+
+    async fn delay_ms(&mut self, _n: u32) {}
+}
+
+/// Empty struct that provides delays by busy-waiting on [`Instant`].
+///
+/// Unlike [`Delay`], this never yields to the scheduler, so delays are not lengthened by the
+/// overhead and latency of sleeping, which makes it suitable for the sub-microsecond timing of
+/// bit-banged protocols. In exchange it keeps a CPU core fully busy for the whole delay, and
+/// can still be stretched if the thread is preempted. Only use it for short delays, well under
+/// 100 µs; prefer [`Delay`] otherwise.
+pub struct SpinDelay;
+
+impl DelayNs for SpinDelay {
+    fn delay_ns(&mut self, n: u32) {
+        let start = Instant::now();
+        let duration = Duration::from_nanos(n.into());
+        while start.elapsed() < duration {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Empty struct that provides delays like [`Delay`], without stalling a tokio runtime when
+/// its blocking delays are used from an async task.
+///
+/// Blocking drivers sometimes have to be called from async code. With [`Delay`], each of
+/// their blocking delays then also blocks the worker thread running the task, and with it
+/// every other task scheduled on that worker. When called from a worker of a multi-threaded
+/// tokio runtime, the blocking delays of `CooperativeDelay` run within
+/// [`tokio::task::block_in_place`] instead, which hands the other tasks of the worker over to
+/// another thread for the duration of the delay. Outside of a runtime, they are plain
+/// [`Delay`]s.
+///
+/// This only limits the damage: the calling task itself is still blocked, and handing over
+/// the worker has a cost, so prefer async drivers, or moving blocking drivers to
+/// [`tokio::task::spawn_blocking`], where possible. On a current-thread runtime, which has no
+/// other thread to hand tasks to, blocking delays still stall the runtime. The async delays
+/// are the same as those of [`Delay`].
+#[cfg(feature = "async-tokio")]
+pub struct CooperativeDelay;
+
+#[cfg(feature = "async-tokio")]
+impl CooperativeDelay {
+    /// Block for `duration`, letting the tokio runtime move other tasks away if called from
+    /// one of its workers.
+    fn sleep(duration: Duration) {
+        use tokio::runtime::{Handle, RuntimeFlavor};
+
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| Delay::sleep(duration))
+            }
+            _ => Delay::sleep(duration),
+        }
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl DelayNs for CooperativeDelay {
+    fn delay_ns(&mut self, n: u32) {
+        CooperativeDelay::sleep(Duration::from_nanos(n.into()));
+    }
+
+    fn delay_us(&mut self, n: u32) {
+        CooperativeDelay::sleep(Duration::from_micros(n.into()));
+    }
+
+    fn delay_ms(&mut self, n: u32) {
+        CooperativeDelay::sleep(Duration::from_millis(n.into()));
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl embedded_hal_async::delay::DelayNs for CooperativeDelay {
+    async fn delay_ns(&mut self, n: u32) {
+        embedded_hal_async::delay::DelayNs::delay_ns(&mut Delay, n).await;
+    }
+
+    async fn delay_us(&mut self, n: u32) {
+        embedded_hal_async::delay::DelayNs::delay_us(&mut Delay, n).await;
+    }
+
+    async fn delay_ms(&mut self, n: u32) {
+        embedded_hal_async::delay::DelayNs::delay_ms(&mut Delay, n).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibrate() {
+        let calibration = Delay::calibrate();
+        assert_eq!(Delay::calibration(), Some(calibration));
+
+        for us in [1, 50, 500, 2000] {
+            let before = Instant::now();
+            Delay.delay_us(us);
+            assert!(before.elapsed() >= Duration::from_micros(us.into()));
+        }
+    }
+
+    #[test]
+    fn test_spin_delay() {
+        let before = Instant::now();
+        SpinDelay.delay_us(50);
+        let elapsed = before.elapsed();
+        assert!(elapsed >= Duration::from_micros(50));
+        assert!(elapsed < Duration::from_millis(50));
+    }
+
+    #[cfg(feature = "async-tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_cooperative_delay() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        // With a single worker, the other task can only run during the delay if the worker
+        // is handed over.
+        let ran = Arc::new(AtomicBool::new(false));
+        let blocking = tokio::spawn({
+            let ran = ran.clone();
+            async move {
+                let other = ran.clone();
+                tokio::spawn(async move { other.store(true, Ordering::SeqCst) });
+                CooperativeDelay.delay_ms(100);
+                ran.load(Ordering::SeqCst)
+            }
+        });
+        assert!(blocking.await.unwrap());
+
+        // Outside of a runtime, this is a plain delay
+        let before = Instant::now();
+        std::thread::spawn(|| CooperativeDelay.delay_ms(10))
+            .join()
+            .unwrap();
+        assert!(before.elapsed() >= Duration::from_millis(10));
+    }
+}
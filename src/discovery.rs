@@ -0,0 +1,349 @@
+//! Discovery of the buses, devices and GPIO chips available on the system
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A bus device node found by [`list_i2c_adapters`] or [`list_spi_devices`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusDevice {
+    /// Path of the device node, e.g. `/dev/i2c-1`
+    pub path: PathBuf,
+    /// Human-readable name reported by the kernel, if any
+    ///
+    /// For I2C adapters this is the adapter name, e.g. `bcm2835 (i2c@7e804000)`. For spidev
+    /// devices it is the driver the device is bound to, e.g. `spidev`.
+    pub name: Option<String>,
+}
+
+/// List the I2C adapters exposed by i2c-dev, in adapter number order.
+///
+/// The adapters are those with a device node in `/dev`, and their names are read from
+/// `/sys/class/i2c-dev`. Entries that cannot be accessed, e.g. for lack of permissions, are
+/// skipped. An error is only returned if `/dev` itself cannot be read.
+#[cfg(feature = "i2c")]
+pub fn list_i2c_adapters() -> io::Result<Vec<BusDevice>> {
+    scan(
+        Path::new("/dev"),
+        Path::new("/sys/class/i2c-dev"),
+        |name| device_numbers(name, "i2c-", 1),
+        |sys| read_attr(&sys.join("name")),
+    )
+}
+
+/// List the spidev devices, ordered by bus and then chip-select number.
+///
+/// The devices are those with a device node in `/dev`, and their drivers are read from
+/// `/sys/class/spidev`. Entries that cannot be accessed, e.g. for lack of permissions, are
+/// skipped. An error is only returned if `/dev` itself cannot be read.
+#[cfg(feature = "spi")]
+pub fn list_spi_devices() -> io::Result<Vec<BusDevice>> {
+    scan(
+        Path::new("/dev"),
+        Path::new("/sys/class/spidev"),
+        |name| device_numbers(name, "spidev", 2),
+        |sys| {
+            let driver = fs::read_link(sys.join("device/driver")).ok()?;
+            Some(driver.file_name()?.to_str()?.to_owned())
+        },
+    )
+}
+
+/// A GPIO chip found by [`list_gpiochips`]
+#[cfg(feature = "gpio_cdev")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpioChipInfo {
+    /// Path of the character device, e.g. `/dev/gpiochip0`
+    pub path: PathBuf,
+    /// Name of the chip in the kernel, e.g. `gpiochip0`
+    pub name: String,
+    /// Functional name of the chip, e.g. `pinctrl-bcm2835`; might be empty
+    ///
+    /// Unlike the device node, this is stable across boots and boards, so it is the way to
+    /// find a given chip.
+    pub label: String,
+    /// Names of the lines, indexed by offset; `None` for unnamed lines
+    ///
+    /// The number of lines of the chip is the length of this vector.
+    pub line_names: Vec<Option<String>>,
+}
+
+#[cfg(feature = "gpio_cdev")]
+impl GpioChipInfo {
+    /// Number of lines of the chip
+    pub fn num_lines(&self) -> u32 {
+        self.line_names.len() as u32
+    }
+
+    /// Offset of the line called `name`, if the chip has one
+    pub fn find_line(&self, name: &str) -> Option<u32> {
+        let offset = self
+            .line_names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))?;
+        Some(offset as u32)
+    }
+}
+
+/// List the GPIO chips exposed by the GPIO character device, in chip number order.
+///
+/// Chips that cannot be opened, e.g. for lack of permissions, are skipped. The name of a line
+/// whose information cannot be queried is reported as `None`. An error is only returned if
+/// `/dev` itself cannot be read.
+#[cfg(feature = "gpio_cdev")]
+pub fn list_gpiochips() -> io::Result<Vec<GpioChipInfo>> {
+    let nodes = scan(
+        Path::new("/dev"),
+        Path::new("/sys/bus/gpio/devices"),
+        |name| device_numbers(name, "gpiochip", 1),
+        |_| None,
+    )?;
+    Ok(nodes
+        .into_iter()
+        .filter_map(|node| {
+            let chip = gpio_cdev::Chip::new(&node.path).ok()?;
+            let line_names = chip
+                .lines()
+                .map(|line| line.info().ok()?.name().map(str::to_owned))
+                .collect();
+            Some(GpioChipInfo {
+                path: node.path,
+                name: chip.name().to_owned(),
+                label: chip.label().to_owned(),
+                line_names,
+            })
+        })
+        .collect())
+}
+
+/// Map a legacy sysfs GPIO number to the character device of its chip and the offset of the
+/// line on that chip, for use with [`CdevPin`](crate::CdevPin).
+///
+/// The chips registered in `/sys/class/gpio` are searched for the one whose range of numbers,
+/// from `base` and spanning `ngpio` lines, contains `sysfs_number`. This requires the kernel
+/// to still provide the sysfs GPIO interface. An error of kind [`io::ErrorKind::NotFound`] is
+/// returned if no chip covers the number.
+#[cfg(feature = "gpio_cdev")]
+pub fn sysfs_to_cdev(sysfs_number: u64) -> io::Result<(PathBuf, u32)> {
+    sysfs_to_cdev_in(
+        Path::new("/sys/class/gpio"),
+        Path::new("/dev"),
+        sysfs_number,
+    )
+}
+
+#[cfg(feature = "gpio_cdev")]
+fn sysfs_to_cdev_in(class: &Path, dev: &Path, sysfs_number: u64) -> io::Result<(PathBuf, u32)> {
+    let parse = |path: PathBuf| -> Option<u64> { read_attr(&path)?.parse().ok() };
+    for entry in fs::read_dir(class)? {
+        let Ok(entry) = entry else { continue };
+        // Named after the base of the chip, not its character device
+        if !entry.file_name().to_string_lossy().starts_with("gpiochip") {
+            continue;
+        }
+        let sys = entry.path();
+        let (Some(base), Some(ngpio)) = (parse(sys.join("base")), parse(sys.join("ngpio"))) else {
+            continue;
+        };
+        if !(base..base + ngpio).contains(&sysfs_number) {
+            continue;
+        }
+        // The chip's `device` links to the GPIO device, named after its character device
+        let Ok(device) = fs::read_link(sys.join("device")) else {
+            continue;
+        };
+        let Some(name) = device.file_name() else {
+            continue;
+        };
+        return Ok((dev.join(name), (sysfs_number - base) as u32));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no GPIO chip covers sysfs GPIO {}", sysfs_number),
+    ))
+}
+
+/// Collect the nodes of `dev` whose name `numbers` accepts, sorted by the numbers `numbers`
+/// parses, named by `name` from the entry of the same name in the sysfs class directory
+/// `class`.
+fn scan(
+    dev: &Path,
+    class: &Path,
+    numbers: impl Fn(&str) -> Option<Vec<u32>>,
+    name: impl Fn(&Path) -> Option<String>,
+) -> io::Result<Vec<BusDevice>> {
+    let mut found: Vec<(Vec<u32>, String)> = Vec::new();
+    for entry in fs::read_dir(dev)? {
+        let Some(file_name) = entry.ok().and_then(|e| e.file_name().into_string().ok()) else {
+            continue;
+        };
+        if let Some(key) = numbers(&file_name) {
+            found.push((key, file_name));
+        }
+    }
+    found.sort();
+
+    Ok(found
+        .into_iter()
+        .filter_map(|(_, file_name)| {
+            let path = dev.join(&file_name);
+            // Skip nodes that vanished since or that we may not even look at
+            fs::metadata(&path).ok()?;
+            Some(BusDevice {
+                path,
+                name: name(&class.join(&file_name)),
+            })
+        })
+        .collect())
+}
+
+/// Parse the `count` dot-separated numbers following `prefix` in a device node name, such
+/// as the bus and chip-select numbers of `spidev0.1`.
+fn device_numbers(name: &str, prefix: &str, count: usize) -> Option<Vec<u32>> {
+    let numbers = name
+        .strip_prefix(prefix)?
+        .split('.')
+        .map(|n| match n.bytes().all(|b| b.is_ascii_digit()) {
+            true => n.parse().ok(),
+            false => None,
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    (numbers.len() == count).then_some(numbers)
+}
+
+/// Read a sysfs attribute, without its trailing newline
+#[cfg(any(test, feature = "i2c", feature = "gpio_cdev"))]
+fn read_attr(path: &Path) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    Some(value.trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "linux-embedded-hal-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_device_numbers() {
+        assert_eq!(device_numbers("i2c-1", "i2c-", 1), Some(vec![1]));
+        assert_eq!(device_numbers("i2c-10", "i2c-", 1), Some(vec![10]));
+        assert_eq!(device_numbers("spidev1.2", "spidev", 2), Some(vec![1, 2]));
+        assert_eq!(device_numbers("i2c-", "i2c-", 1), None);
+        assert_eq!(device_numbers("i2c-+1", "i2c-", 1), None);
+        assert_eq!(device_numbers("i2c-1.0", "i2c-", 1), None);
+        assert_eq!(device_numbers("spidev1", "spidev", 2), None);
+        assert_eq!(device_numbers("tty1", "i2c-", 1), None);
+    }
+
+    #[cfg(feature = "gpio_cdev")]
+    #[test]
+    fn test_find_line() {
+        let chip = GpioChipInfo {
+            path: PathBuf::from("/dev/gpiochip0"),
+            name: "gpiochip0".to_owned(),
+            label: "pinctrl".to_owned(),
+            line_names: vec![None, Some("LED".to_owned()), Some("BUTTON".to_owned())],
+        };
+        assert_eq!(chip.num_lines(), 3);
+        assert_eq!(chip.find_line("BUTTON"), Some(2));
+        assert_eq!(chip.find_line("RELAY"), None);
+    }
+
+    #[cfg(feature = "gpio_cdev")]
+    #[test]
+    fn test_sysfs_to_cdev() {
+        let root = TempDir::new("sysfs-to-cdev");
+        let class = root.0.join("class");
+        let devices = root.0.join("devices");
+        for (base, ngpio, name) in [(512, 54, "gpiochip0"), (570, 8, "gpiochip1")] {
+            let sys = class.join(format!("gpiochip{}", base));
+            fs::create_dir_all(&sys).unwrap();
+            fs::create_dir_all(devices.join(name)).unwrap();
+            fs::write(sys.join("base"), format!("{}\n", base)).unwrap();
+            fs::write(sys.join("ngpio"), format!("{}\n", ngpio)).unwrap();
+            std::os::unix::fs::symlink(devices.join(name), sys.join("device")).unwrap();
+        }
+        fs::create_dir_all(class.join("export")).unwrap();
+        // A chip without a `device` link doesn't end the search
+        let broken = class.join("gpiochip9999");
+        fs::create_dir_all(&broken).unwrap();
+        fs::write(broken.join("base"), "512\n").unwrap();
+        fs::write(broken.join("ngpio"), "1\n").unwrap();
+
+        let dev = Path::new("/dev");
+        let map = |n| sysfs_to_cdev_in(&class, dev, n);
+        assert_eq!(map(512).unwrap(), (dev.join("gpiochip0"), 0));
+        assert_eq!(map(529).unwrap(), (dev.join("gpiochip0"), 17));
+        assert_eq!(map(570).unwrap(), (dev.join("gpiochip1"), 0));
+        assert_eq!(map(577).unwrap(), (dev.join("gpiochip1"), 7));
+        assert_eq!(map(578).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(map(17).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_scan() {
+        let root = TempDir::new("discovery");
+        let dev = root.0.join("dev");
+        let class = root.0.join("class");
+        for node in ["i2c-10", "i2c-2", "i2c-x", "tty0"] {
+            fs::create_dir_all(dev.join(node)).unwrap();
+        }
+        // Listed in sysfs only: no node to return
+        fs::create_dir_all(class.join("i2c-3")).unwrap();
+        fs::create_dir_all(class.join("i2c-2")).unwrap();
+        fs::write(class.join("i2c-2/name"), "adapter two\n").unwrap();
+
+        let found = scan(
+            &dev,
+            &class,
+            |name| device_numbers(name, "i2c-", 1),
+            |sys| read_attr(&sys.join("name")),
+        )
+        .unwrap();
+        assert_eq!(
+            found,
+            [
+                BusDevice {
+                    path: dev.join("i2c-2"),
+                    name: Some("adapter two".to_owned()),
+                },
+                BusDevice {
+                    path: dev.join("i2c-10"),
+                    name: None,
+                },
+            ]
+        );
+
+        // A missing sysfs class is not an error
+        let found = scan(
+            &dev,
+            &root.0.join("missing"),
+            |name| device_numbers(name, "i2c-", 1),
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(scan(&root.0.join("missing"), &class, |_| None, |_| None).is_err());
+    }
+}
@@ -2,12 +2,17 @@
 //!
 //! [`embedded-hal`]: https://docs.rs/embedded-hal
 
+use std::convert::TryFrom;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
 
 use embedded_hal::i2c::NoAcknowledgeSource;
 
+use crate::retry::{retry_interrupted, Interrupted};
+
 /// Newtype around [`i2cdev::linux::LinuxI2CDevice`] that implements the `embedded-hal` traits
 ///
 /// [`i2cdev::linux::LinuxI2CDevice`]: https://docs.rs/i2cdev/0.5.0/i2cdev/linux/struct.LinuxI2CDevice.html
@@ -15,33 +20,380 @@ pub struct I2cdev {
     inner: i2cdev::linux::LinuxI2CDevice,
     path: PathBuf,
     address: Option<u16>,
+    pec: bool,
+    nonblocking: bool,
+    hotplug: bool,
+    /// The adapter lacks plain I2C transfers, so transactions are emulated with SMBus calls
+    smbus_only: bool,
+    /// `address` is a 10-bit address
+    ten_bit: bool,
 }
 
 impl I2cdev {
     /// See [`i2cdev::linux::LinuxI2CDevice::new`][0] for details.
     ///
+    /// Adapters that only support SMBus transfers, such as some USB bridges, are detected
+    /// from their functionality flags. Transactions on them are emulated with SMBus calls,
+    /// which is only possible for some shapes of transactions, see [`I2c::transaction`].
+    ///
     /// [0]: https://docs.rs/i2cdev/0.5.0/i2cdev/linux/struct.LinuxI2CDevice.html#method.new
+    /// [`I2c::transaction`]: embedded_hal::i2c::I2c::transaction
     pub fn new<P>(path: P) -> Result<Self, i2cdev::linux::LinuxI2CError>
     where
         P: AsRef<Path>,
     {
+        let inner = i2cdev::linux::LinuxI2CDevice::new(&path, 0)?;
+        // If the flags can't be queried, try plain I2C transfers anyway
+        let smbus_only =
+            matches!(functionality(inner.as_raw_fd()), Ok(funcs) if funcs & I2C_FUNC_I2C == 0);
         let dev = I2cdev {
             path: path.as_ref().to_path_buf(),
-            inner: i2cdev::linux::LinuxI2CDevice::new(path, 0)?,
+            inner,
             address: None,
+            pec: false,
+            nonblocking: false,
+            hotplug: false,
+            smbus_only,
+            ten_bit: false,
         };
         Ok(dev)
     }
 
-    fn set_address(&mut self, address: u16) -> Result<(), i2cdev::linux::LinuxI2CError> {
-        if self.address != Some(address) {
-            self.inner = i2cdev::linux::LinuxI2CDevice::new(&self.path, address)?;
+    /// Open the adapter numbered `bus`, i.e. `/dev/i2c-{bus}`, like [`I2cdev::new`].
+    ///
+    /// Adapter numbers are those listed by `i2cdetect -l`, and found in the paths returned by
+    /// [`list_i2c_adapters`]. If the device node doesn't exist, e.g. because there is no such
+    /// adapter or the `i2c-dev` module isn't loaded, the error is of kind
+    /// [`std::io::ErrorKind::NotFound`] and names the bus and the path.
+    ///
+    /// [`list_i2c_adapters`]: crate::list_i2c_adapters
+    pub fn from_bus_number(bus: u32) -> Result<Self, i2cdev::linux::LinuxI2CError> {
+        use i2cdev::linux::LinuxI2CError;
+        use std::io;
+
+        let path = PathBuf::from(format!("/dev/i2c-{}", bus));
+        I2cdev::new(&path).map_err(|err| match err {
+            LinuxI2CError::Io(err) if err.kind() == io::ErrorKind::NotFound => {
+                LinuxI2CError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no I2C bus {}: {} does not exist", bus, path.display()),
+                ))
+            }
+            err => err,
+        })
+    }
+
+    /// Like [`I2cdev::new`], for adapters or devices that may disappear and reappear, such as
+    /// USB adapters or devices on hot-pluggable connectors.
+    ///
+    /// When a transaction fails because the adapter or device is missing, the device file is
+    /// reopened and the transaction attempted once more before the error is returned. Errors
+    /// while reopening are returned instead. Missing adapters are detected from `ENODEV`
+    /// errors, or `ENXIO` errors if the device node is gone too: `ENXIO` is also what a device
+    /// not acknowledging its address gives, and such failures are not retried.
+    pub fn new_hotplug<P>(path: P) -> Result<Self, i2cdev::linux::LinuxI2CError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut dev = I2cdev::new(path)?;
+        dev.hotplug = true;
+        Ok(dev)
+    }
+
+    /// Enable or disable SMBus Packet Error Checking (PEC).
+    ///
+    /// When enabled, the kernel appends a CRC-8 to SMBus transfers and validates the one
+    /// received from the device, reporting mismatches as errors. PEC only applies to the SMBus
+    /// transfers available through the inner [`LinuxI2CDevice`][0], not to the plain I2C
+    /// transactions of the `embedded-hal` traits. The adapter must support it, as advertised
+    /// by the `I2C_FUNC_SMBUS_PEC` functionality flag.
+    ///
+    /// The setting is kept when the device is reopened for another address.
+    ///
+    /// See [`i2cdev::linux::LinuxI2CDevice::set_smbus_pec`][1] for details.
+    ///
+    /// [0]: https://docs.rs/i2cdev/0.6.0/i2cdev/linux/struct.LinuxI2CDevice.html
+    /// [1]: https://docs.rs/i2cdev/0.6.0/i2cdev/linux/struct.LinuxI2CDevice.html#method.set_smbus_pec
+    pub fn set_pec(&mut self, enabled: bool) -> Result<(), I2CError> {
+        self.inner.set_smbus_pec(enabled)?;
+        self.pec = enabled;
+        Ok(())
+    }
+
+    /// Explicitly set or clear the `O_NONBLOCK` flag of the i2c-dev file descriptor.
+    ///
+    /// The flag is otherwise left as the file was opened, which is blocking. The setting is
+    /// kept when the device is reopened for another address.
+    ///
+    /// Note that i2c-dev currently does not honour `O_NONBLOCK` for transfers, which always
+    /// run to completion. `EAGAIN` therefore keeps meaning that the adapter lost arbitration
+    /// and is reported as [`ErrorKind::ArbitrationLoss`] in either mode.
+    ///
+    /// [`ErrorKind::ArbitrationLoss`]: embedded_hal::i2c::ErrorKind::ArbitrationLoss
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), I2CError> {
+        set_fd_nonblocking(self.inner.as_raw_fd(), nonblocking)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    /// Best-effort query of the SCL clock frequency the adapter runs at, in Hz.
+    ///
+    /// i2c-dev offers no ioctl for this, so the `clock-frequency` property of the adapter's
+    /// device tree node is read from sysfs, under
+    /// `/sys/bus/i2c/devices/i2c-N/of_node/` (or that of its parent controller device).
+    /// Returns `None` if the adapter has no such property, e.g. on systems without a device
+    /// tree, or if the adapter number cannot be derived from the device path.
+    pub fn bus_clock_hz(&self) -> Option<u32> {
+        let adapter = adapter_name(&self.path)?;
+        let dir = Path::new("/sys/bus/i2c/devices").join(adapter);
+        [
+            dir.join("of_node/clock-frequency"),
+            dir.join("device/of_node/clock-frequency"),
+        ]
+        .iter()
+        .find_map(|p| parse_clock_frequency(&std::fs::read(p).ok()?))
+    }
+
+    /// Read the byte register `command` of the device at `address` with an SMBus read byte
+    /// data transfer.
+    ///
+    /// Fails with `EOPNOTSUPP` if the adapter doesn't support it (the
+    /// `I2C_FUNC_SMBUS_READ_BYTE_DATA` functionality flag).
+    pub fn smbus_read_byte_data(&mut self, address: u8, command: u8) -> Result<u8, I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_READ_BYTE_DATA)?;
+        Ok(self.inner.smbus_read_byte_data(command)?)
+    }
+
+    /// Write `value` to the byte register `command` of the device at `address` with an SMBus
+    /// write byte data transfer.
+    ///
+    /// Fails with `EOPNOTSUPP` if the adapter doesn't support it (the
+    /// `I2C_FUNC_SMBUS_WRITE_BYTE_DATA` functionality flag).
+    pub fn smbus_write_byte_data(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u8,
+    ) -> Result<(), I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_WRITE_BYTE_DATA)?;
+        Ok(self.inner.smbus_write_byte_data(command, value)?)
+    }
+
+    /// Read the word register `command` of the device at `address` with an SMBus read word
+    /// data transfer.
+    ///
+    /// As specified by SMBus, the low byte of the word is the first one on the bus. Devices
+    /// sending the high byte first, as many sensors do, need the result
+    /// [swapped](u16::swap_bytes). Fails with `EOPNOTSUPP` if the adapter doesn't support it
+    /// (the `I2C_FUNC_SMBUS_READ_WORD_DATA` functionality flag).
+    pub fn smbus_read_word_data(&mut self, address: u8, command: u8) -> Result<u16, I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_READ_WORD_DATA)?;
+        Ok(self.inner.smbus_read_word_data(command)?)
+    }
+
+    /// Write `value` to the word register `command` of the device at `address` with an SMBus
+    /// write word data transfer.
+    ///
+    /// As specified by SMBus, the low byte of `value` is sent first. Fails with `EOPNOTSUPP` if
+    /// the adapter doesn't support it (the `I2C_FUNC_SMBUS_WRITE_WORD_DATA` functionality
+    /// flag).
+    pub fn smbus_write_word_data(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u16,
+    ) -> Result<(), I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_WRITE_WORD_DATA)?;
+        Ok(self.inner.smbus_write_word_data(command, value)?)
+    }
+
+    /// Perform an SMBus process call to the device at `address`: write `value` to the
+    /// register `command` and read a word back, in a single transaction.
+    ///
+    /// Fails with `EOPNOTSUPP` if the adapter advertises neither native support nor SMBus
+    /// emulation for process calls (the `I2C_FUNC_SMBUS_PROC_CALL` functionality flag).
+    pub fn smbus_process_call(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u16,
+    ) -> Result<u16, I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_PROC_CALL)?;
+        Ok(self.inner.smbus_process_word(command, value)?)
+    }
+
+    /// Perform an SMBus block process call to the device at `address`: write the block
+    /// `values` to the register `command` and read a block back, in a single transaction.
+    ///
+    /// At most 31 bytes can be written; longer blocks are rejected with `EINVAL`. Fails with
+    /// `EOPNOTSUPP` if the adapter doesn't support block process calls (the
+    /// `I2C_FUNC_SMBUS_BLOCK_PROC_CALL` functionality flag).
+    pub fn smbus_block_process_call(
+        &mut self,
+        address: u8,
+        command: u8,
+        values: &[u8],
+    ) -> Result<Vec<u8>, I2CError> {
+        use i2cdev::core::I2CDevice;
+
+        // i2cdev silently truncates longer blocks
+        if values.len() > MAX_BLOCK_PROC_CALL_LEN {
+            return Err(errno_error(nix::errno::Errno::EINVAL));
+        }
+        self.set_address(u16::from(address), false)?;
+        self.check_functionality(I2C_FUNC_SMBUS_BLOCK_PROC_CALL)?;
+        Ok(self.inner.smbus_process_block(command, values)?)
+    }
+
+    /// Fail with `EOPNOTSUPP` unless the adapter advertises all of the functionality `flags`.
+    fn check_functionality(&self, flags: nix::libc::c_ulong) -> Result<(), I2CError> {
+        let funcs = functionality(self.inner.as_raw_fd()).map_err(errno_error)?;
+        if funcs & flags != flags {
+            return Err(errno_error(nix::errno::Errno::EOPNOTSUPP));
+        }
+        Ok(())
+    }
+
+    /// Close and reopen the device file for the next transaction
+    fn reopen(&mut self, address: u16) -> Result<(), i2cdev::linux::LinuxI2CError> {
+        self.address = None;
+        self.set_address(address, self.ten_bit)
+    }
+
+    /// Direct the next transactions to the device at `address`, a 10-bit address if
+    /// `ten_bit` is set.
+    fn set_address(
+        &mut self,
+        address: u16,
+        ten_bit: bool,
+    ) -> Result<(), i2cdev::linux::LinuxI2CError> {
+        if self.address != Some(address) || self.ten_bit != ten_bit {
+            self.inner = if ten_bit {
+                // The kernel only accepts addresses above 0x7f once the file is in 10-bit mode
+                let mut inner = i2cdev::linux::LinuxI2CDevice::new(&self.path, 0)?;
+                // SAFETY: I2C_TENBIT takes its argument by value
+                unsafe { i2c_tenbit(inner.as_raw_fd(), 1) }
+                    .map_err(|e| i2cdev::linux::LinuxI2CError::Errno(e as i32))?;
+                inner.set_slave_address(address)?;
+                inner
+            } else {
+                i2cdev::linux::LinuxI2CDevice::new(&self.path, address)?
+            };
             self.address = Some(address);
+            self.ten_bit = ten_bit;
+            if self.pec {
+                self.inner.set_smbus_pec(true)?;
+            }
+            if self.nonblocking {
+                set_fd_nonblocking(self.inner.as_raw_fd(), true)?;
+            }
         }
         Ok(())
     }
 }
 
+/// Functionality flag for plain I2C transfers, from `linux/i2c.h`
+const I2C_FUNC_I2C: nix::libc::c_ulong = 0x0000_0001;
+/// Functionality flag for SMBus read byte data transfers, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_READ_BYTE_DATA: nix::libc::c_ulong = 0x0008_0000;
+/// Functionality flag for SMBus write byte data transfers, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_WRITE_BYTE_DATA: nix::libc::c_ulong = 0x0010_0000;
+/// Functionality flag for SMBus read word data transfers, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_READ_WORD_DATA: nix::libc::c_ulong = 0x0020_0000;
+/// Functionality flag for SMBus write word data transfers, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_WRITE_WORD_DATA: nix::libc::c_ulong = 0x0040_0000;
+/// Functionality flag for SMBus process calls, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_PROC_CALL: nix::libc::c_ulong = 0x0080_0000;
+/// Functionality flag for SMBus block process calls, from `linux/i2c.h`
+const I2C_FUNC_SMBUS_BLOCK_PROC_CALL: nix::libc::c_ulong = 0x0000_8000;
+/// Largest block of an SMBus transfer
+const SMBUS_BLOCK_MAX: usize = 32;
+/// Largest block accepted by i2cdev for block process calls
+const MAX_BLOCK_PROC_CALL_LEN: usize = 31;
+/// Largest number of messages of an `I2C_RDWR` call, `I2C_RDWR_IOCTL_MAX_MSGS` in
+/// `linux/i2c-dev.h`
+const MAX_MESSAGES: usize = 42;
+
+// Query the functionality flags of the adapter
+nix::ioctl_read_bad!(i2c_funcs, 0x0705, nix::libc::c_ulong);
+// Enable or disable 10-bit addressing for the file
+nix::ioctl_write_int_bad!(i2c_tenbit, 0x0704);
+
+/// Query the functionality flags of the adapter open as `fd`.
+fn functionality(fd: RawFd) -> nix::Result<nix::libc::c_ulong> {
+    let mut funcs = 0;
+    // SAFETY: I2C_FUNCS only writes an unsigned long to the pointer passed
+    unsafe { i2c_funcs(fd, &mut funcs) }?;
+    Ok(funcs)
+}
+
+/// Wrap `errno` in an [`I2CError`].
+fn errno_error(errno: nix::errno::Errno) -> I2CError {
+    I2CError::from(i2cdev::linux::LinuxI2CError::Errno(errno as i32))
+}
+
+/// Set or clear `O_NONBLOCK` on `fd`, leaving its other status flags untouched.
+fn set_fd_nonblocking(fd: RawFd, nonblocking: bool) -> Result<(), i2cdev::linux::LinuxI2CError> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+    let errno = |e: nix::errno::Errno| i2cdev::linux::LinuxI2CError::Errno(e as i32);
+    let mut flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).map_err(errno)?);
+    flags.set(OFlag::O_NONBLOCK, nonblocking);
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(errno)?;
+    Ok(())
+}
+
+/// Derive the adapter name (`i2c-N`) from an i2c-dev path such as `/dev/i2c-N`, following
+/// symlinks.
+fn adapter_name(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name()?.to_str()?;
+    let number = name.strip_prefix("i2c-")?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(name.to_owned())
+}
+
+/// Parse a device tree `clock-frequency` property, a single big-endian `u32` cell.
+fn parse_clock_frequency(raw: &[u8]) -> Option<u32> {
+    let cell = <[u8; 4]>::try_from(raw).ok()?;
+    Some(u32::from_be_bytes(cell))
+}
+
+/// Devices are identified by the path they were opened with and the address of the last
+/// transaction, not by file descriptor. As a transaction to another address changes the
+/// identity, don't perform any on a device while it is used as a key.
+impl PartialEq for I2cdev {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.address == other.address
+    }
+}
+
+impl Eq for I2cdev {}
+
+impl Hash for I2cdev {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.address.hash(state);
+    }
+}
+
 impl ops::Deref for I2cdev {
     type Target = i2cdev::linux::LinuxI2CDevice;
 
@@ -60,33 +412,163 @@ mod embedded_hal_impl {
     use super::*;
     use embedded_hal::i2c::ErrorType;
     use embedded_hal::i2c::{I2c, Operation as I2cOperation, SevenBitAddress, TenBitAddress};
-    use i2cdev::core::{I2CMessage, I2CTransfer};
+    use i2cdev::core::{I2CDevice, I2CMessage, I2CTransfer};
     use i2cdev::linux::LinuxI2CMessage;
     impl ErrorType for I2cdev {
         type Error = I2CError;
     }
 
     impl I2c<TenBitAddress> for I2cdev {
+        /// Perform a transaction against the device at `address`. [Read more][transaction]
+        ///
+        /// A transaction made of a single empty read or write, as used to probe for the
+        /// presence of a device, is sent as an SMBus quick command with the corresponding
+        /// direction bit. Zero-length messages are not supported by every adapter, whereas
+        /// quick commands are also available on SMBus-only adapters. Empty operations within
+        /// longer transactions are sent as zero-length messages.
+        ///
+        /// On adapters that only support SMBus transfers, the transaction is emulated with the
+        /// SMBus call of the same shape, if there is one:
+        ///
+        /// - a single empty read or write: quick command;
+        /// - a single one-byte read or write: read or write byte;
+        /// - a single write of a command byte and one or two data bytes: write byte or word
+        ///   data, with the word least significant byte first;
+        /// - a single write of a command byte and up to 32 more bytes: I2C block write;
+        /// - a one-byte command write followed by a read of one, two or up to 32 bytes: read
+        ///   byte data, read word data or I2C block read.
+        ///
+        /// Other transactions, such as reads longer than one byte without a command, empty
+        /// reads after a command, or writes followed by several reads, fail with `EOPNOTSUPP`.
+        /// Adapters may not implement all SMBus calls either.
+        ///
+        /// Other transactions are sent in a single `I2C_RDWR` call, with one message per
+        /// operation and repeated starts between them. The kernel accepts at most 42 messages
+        /// per call, so longer transactions fail with an error of kind
+        /// [`InvalidInput`](std::io::ErrorKind::InvalidInput) without sending anything.
+        /// They are not split, as that would put stop conditions between operations that the
+        /// device may expect to be part of a single transfer.
+        ///
+        /// `address` is a 10-bit address, and every message is sent with the `I2C_M_TEN`
+        /// flag. The adapter must support 10-bit addressing (`I2C_FUNC_10BIT_ADDR`). Use the
+        /// [`SevenBitAddress`] implementation for the usual 7-bit addresses.
+        ///
+        /// [transaction]: I2c::transaction
         fn transaction(
             &mut self,
             address: u16,
             operations: &mut [I2cOperation],
         ) -> Result<(), Self::Error> {
-            // Map operations from generic to linux objects
-            let mut messages: Vec<_> = operations
-                .as_mut()
-                .iter_mut()
-                .map(|a| match a {
-                    I2cOperation::Write(w) => LinuxI2CMessage::write(w),
-                    I2cOperation::Read(r) => LinuxI2CMessage::read(r),
-                })
-                .collect();
-
-            self.set_address(address)?;
-            self.inner
-                .transfer(&mut messages)
-                .map(drop)
-                .map_err(|err| I2CError { err })
+            self.run_transaction(address, true, operations).map(drop)
+        }
+    }
+
+    impl I2cdev {
+        /// Perform a transaction like [`I2c::transaction`], and return the number of bytes
+        /// transferred by each operation.
+        ///
+        /// The kernel reports how many messages of the transaction it performed. Messages are
+        /// either transferred in full or not at all, so the count of an operation is either
+        /// the length of its buffer or, for operations the adapter didn't get to, zero. Most
+        /// adapters fail the whole transaction rather than stopping early, but some report a
+        /// partial transaction as success, which this makes visible.
+        pub fn transaction_counted(
+            &mut self,
+            address: u8,
+            operations: &mut [I2cOperation],
+        ) -> Result<Vec<usize>, I2CError> {
+            let done = self.run_transaction(u16::from(address), false, operations)?;
+            Ok(transferred_lengths(operations, done))
+        }
+
+        /// Perform a transaction, returning the number of messages transferred.
+        fn run_transaction(
+            &mut self,
+            address: u16,
+            ten_bit: bool,
+            operations: &mut [I2cOperation],
+        ) -> Result<usize, I2CError> {
+            self.set_address(address, ten_bit)?;
+            let mut res = perform(&mut self.inner, operations, self.smbus_only, ten_bit);
+            let path = &self.path;
+            if self.hotplug && matches!(&res, Err(err) if is_missing(err, || !path.exists())) {
+                self.reopen(address)?;
+                res = perform(&mut self.inner, operations, self.smbus_only, ten_bit);
+            }
+            res.map_err(|err| I2CError { err })
+        }
+    }
+
+    /// Perform `operations` on `inner`, returning the number of messages transferred.
+    fn perform(
+        inner: &mut i2cdev::linux::LinuxI2CDevice,
+        operations: &mut [I2cOperation],
+        smbus_only: bool,
+        ten_bit: bool,
+    ) -> Result<usize, i2cdev::linux::LinuxI2CError> {
+        if smbus_only {
+            return retry_interrupted(|| match smbus_transaction(inner, operations) {
+                Some(res) => res.map(|()| operations.len()),
+                None => Err(i2cdev::linux::LinuxI2CError::Errno(
+                    nix::errno::Errno::EOPNOTSUPP as i32,
+                )),
+            });
+        }
+        if let Some(read) = quick_command(operations) {
+            return retry_interrupted(|| inner.smbus_write_quick(read)).map(|()| 1);
+        }
+
+        check_message_count(operations.len())?;
+        // Map operations from generic to linux objects
+        let mut messages: Vec<_> = operations
+            .iter_mut()
+            .map(|a| match a {
+                I2cOperation::Write(w) => {
+                    LinuxI2CMessage::write(w).with_flags(message_flags(false, ten_bit))
+                }
+                I2cOperation::Read(r) => {
+                    LinuxI2CMessage::read(r).with_flags(message_flags(true, ten_bit))
+                }
+            })
+            .collect();
+        retry_interrupted(|| inner.transfer(&mut messages)).map(|n| n as usize)
+    }
+
+    impl I2cdev {
+        /// Read `buf.len()` bytes from the 8-bit register `reg` of the device at `address`.
+        ///
+        /// The register address is written and the data read in a single transaction, with
+        /// a repeated start in between.
+        pub fn read_reg(&mut self, address: u8, reg: u8, buf: &mut [u8]) -> Result<(), I2CError> {
+            self.write_read(address, &[reg], buf)
+        }
+
+        /// Write `data` to the 8-bit register `reg` of the device at `address`.
+        ///
+        /// The register address and the data are sent in a single write message.
+        pub fn write_reg(&mut self, address: u8, reg: u8, data: &[u8]) -> Result<(), I2CError> {
+            self.write(address, &[&[reg], data].concat())
+        }
+
+        /// Read `buf.len()` bytes from the 16-bit register `reg` of the device at `address`.
+        ///
+        /// The register address is sent most significant byte first, as most devices with
+        /// 16-bit register addresses, such as EEPROMs, expect.
+        pub fn read_reg16(
+            &mut self,
+            address: u8,
+            reg: u16,
+            buf: &mut [u8],
+        ) -> Result<(), I2CError> {
+            self.write_read(address, &reg.to_be_bytes(), buf)
+        }
+
+        /// Write `data` to the 16-bit register `reg` of the device at `address`.
+        ///
+        /// The register address is sent most significant byte first, followed by the data in
+        /// the same write message.
+        pub fn write_reg16(&mut self, address: u8, reg: u16, data: &[u8]) -> Result<(), I2CError> {
+            self.write(address, &[&reg.to_be_bytes()[..], data].concat())
         }
     }
 
@@ -96,11 +578,144 @@ mod embedded_hal_impl {
             address: u8,
             operations: &mut [I2cOperation],
         ) -> Result<(), Self::Error> {
-            I2c::<TenBitAddress>::transaction(self, u16::from(address), operations)
+            self.run_transaction(u16::from(address), false, operations)
+                .map(drop)
+        }
+    }
+}
+
+/// Fail unless a transaction of `count` operations fits in a single `I2C_RDWR` call.
+fn check_message_count(count: usize) -> Result<(), i2cdev::linux::LinuxI2CError> {
+    if count > MAX_MESSAGES {
+        return Err(i2cdev::linux::LinuxI2CError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "transaction of {} operations exceeds the kernel limit of {} messages",
+                count, MAX_MESSAGES
+            ),
+        )));
+    }
+    Ok(())
+}
+
+impl Interrupted for i2cdev::linux::LinuxI2CError {
+    fn is_interrupted(&self) -> bool {
+        match self {
+            i2cdev::linux::LinuxI2CError::Errno(errno) => *errno == nix::errno::Errno::EINTR as i32,
+            i2cdev::linux::LinuxI2CError::Io(err) => err.is_interrupted(),
         }
     }
 }
 
+/// Flags of an `I2C_RDWR` message, reading if `read` is set and to a 10-bit address if
+/// `ten_bit` is set
+fn message_flags(read: bool, ten_bit: bool) -> i2cdev::linux::I2CMessageFlags {
+    use i2cdev::linux::I2CMessageFlags;
+
+    let mut flags = I2CMessageFlags::empty();
+    flags.set(I2CMessageFlags::READ, read);
+    flags.set(I2CMessageFlags::TEN_BIT_ADDRESS, ten_bit);
+    flags
+}
+
+/// Perform `operations` on `dev` with SMBus calls, or return `None` if they can't be
+/// expressed as one.
+///
+/// A block read returning fewer bytes than requested fails with an
+/// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error stating how many arrived.
+fn smbus_transaction<D>(
+    dev: &mut D,
+    operations: &mut [embedded_hal::i2c::Operation<'_>],
+) -> Option<Result<(), D::Error>>
+where
+    D: i2cdev::core::I2CDevice,
+    D::Error: From<std::io::Error>,
+{
+    use embedded_hal::i2c::Operation::{Read, Write};
+
+    Some(match operations {
+        [Read([])] => dev.smbus_write_quick(true),
+        [Write([])] => dev.smbus_write_quick(false),
+        [Read([byte])] => dev.smbus_read_byte().map(|b| *byte = b),
+        [Write([byte])] => dev.smbus_write_byte(*byte),
+        [Write([command, value])] => dev.smbus_write_byte_data(*command, *value),
+        // SMBus words are sent least significant byte first
+        [Write([command, low, high])] => {
+            dev.smbus_write_word_data(*command, u16::from_le_bytes([*low, *high]))
+        }
+        [Write([command, data @ ..])] if data.len() <= SMBUS_BLOCK_MAX => {
+            dev.smbus_write_i2c_block_data(*command, data)
+        }
+        [Write([command]), Read([byte])] => dev.smbus_read_byte_data(*command).map(|b| *byte = b),
+        [Write([command]), Read(buf)] if buf.len() == 2 => dev
+            .smbus_read_word_data(*command)
+            .map(|word| buf.copy_from_slice(&word.to_le_bytes())),
+        // The kernel rejects empty I2C block reads
+        [Write([command]), Read(buf)] if !buf.is_empty() && buf.len() <= SMBUS_BLOCK_MAX => dev
+            .smbus_read_i2c_block_data(*command, buf.len() as u8)
+            .and_then(|data| {
+                if data.len() != buf.len() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "SMBus block read returned {} of {} bytes",
+                            data.len(),
+                            buf.len()
+                        ),
+                    )
+                    .into());
+                }
+                buf.copy_from_slice(&data);
+                Ok(())
+            }),
+        _ => return None,
+    })
+}
+
+/// The direction bit of the SMBus quick command `operations` amount to, `true` meaning read,
+/// if they consist of a single empty read or write.
+fn quick_command(operations: &[embedded_hal::i2c::Operation<'_>]) -> Option<bool> {
+    use embedded_hal::i2c::Operation;
+
+    match operations {
+        [Operation::Read([])] => Some(true),
+        [Operation::Write([])] => Some(false),
+        _ => None,
+    }
+}
+
+/// Number of bytes transferred by each of `operations`, of which the first `done` were
+/// performed
+fn transferred_lengths(operations: &[embedded_hal::i2c::Operation<'_>], done: usize) -> Vec<usize> {
+    use embedded_hal::i2c::Operation;
+
+    operations
+        .iter()
+        .enumerate()
+        .map(|(i, op)| match op {
+            _ if i >= done => 0,
+            Operation::Read(buf) => buf.len(),
+            Operation::Write(buf) => buf.len(),
+        })
+        .collect()
+}
+
+/// Whether `err` means that the adapter or the addressed device has gone missing
+///
+/// `ENXIO` is also the error of an address that is not acknowledged, so it only counts if
+/// `node_gone` confirms that the device node has disappeared.
+fn is_missing(err: &i2cdev::linux::LinuxI2CError, node_gone: impl FnOnce() -> bool) -> bool {
+    let errno = match err {
+        i2cdev::linux::LinuxI2CError::Errno(errno) => Some(*errno),
+        i2cdev::linux::LinuxI2CError::Io(err) => err.raw_os_error(),
+    };
+    match errno.map(nix::errno::Errno::from_i32) {
+        Some(nix::errno::Errno::ENODEV) => true,
+        Some(nix::errno::Errno::ENXIO) => node_gone(),
+        _ => false,
+    }
+}
+
 /// Error type wrapping [LinuxI2CError](i2cdev::linux::LinuxI2CError) to implement [embedded_hal::i2c::ErrorKind]
 #[derive(Debug)]
 pub struct I2CError {
@@ -120,6 +735,15 @@ impl From<i2cdev::linux::LinuxI2CError> for I2CError {
     }
 }
 
+impl From<I2CError> for std::io::Error {
+    fn from(err: I2CError) -> Self {
+        match err.err {
+            i2cdev::linux::LinuxI2CError::Errno(errno) => std::io::Error::from_raw_os_error(errno),
+            i2cdev::linux::LinuxI2CError::Io(err) => err,
+        }
+    }
+}
+
 impl fmt::Display for I2CError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.err)
@@ -147,11 +771,363 @@ impl embedded_hal::i2c::Error for I2CError {
 
         // https://www.kernel.org/doc/html/latest/i2c/fault-codes.html
         match errno {
-            EBUSY | EINVAL | EIO => ErrorKind::Bus,
+            EBUSY | EINVAL | EIO | ETIMEDOUT => ErrorKind::Bus,
             EAGAIN => ErrorKind::ArbitrationLoss,
             ENODEV => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
             ENXIO => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            // Used by several adapter drivers to report a NACK without
+            // telling which phase of the transfer it happened in.
+            EREMOTEIO => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
             _ => ErrorKind::Other,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{Error, ErrorKind};
+    use i2cdev::linux::LinuxI2CError;
+    use nix::errno::Errno;
+
+    fn kind(errno: Errno) -> ErrorKind {
+        I2CError::from(LinuxI2CError::Errno(errno as i32)).kind()
+    }
+
+    #[test]
+    fn test_error_kind() {
+        assert_eq!(kind(Errno::EIO), ErrorKind::Bus);
+        assert_eq!(kind(Errno::ETIMEDOUT), ErrorKind::Bus);
+        assert_eq!(kind(Errno::EAGAIN), ErrorKind::ArbitrationLoss);
+        assert_eq!(
+            kind(Errno::ENXIO),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        assert_eq!(
+            kind(Errno::ENODEV),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)
+        );
+        assert_eq!(
+            kind(Errno::EREMOTEIO),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+        );
+        assert_eq!(kind(Errno::EPERM), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut attempts = 0;
+        let res = retry_interrupted(|| {
+            attempts += 1;
+            match attempts {
+                1 => Err(LinuxI2CError::Errno(Errno::EINTR as i32)),
+                2 => Err(LinuxI2CError::Io(std::io::Error::from(
+                    std::io::ErrorKind::Interrupted,
+                ))),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        let mut attempts = 0;
+        let res: Result<(), _> = retry_interrupted(|| {
+            attempts += 1;
+            Err(LinuxI2CError::Errno(Errno::ENXIO as i32))
+        });
+        assert!(matches!(res, Err(LinuxI2CError::Errno(e)) if e == Errno::ENXIO as i32));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_from_bus_number_missing() {
+        let err = match I2cdev::from_bus_number(u32::MAX) {
+            Err(i2cdev::linux::LinuxI2CError::Io(err)) => err,
+            _ => panic!("expected an I/O error"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("/dev/i2c-4294967295"));
+    }
+
+    #[test]
+    fn test_adapter_name() {
+        assert_eq!(
+            adapter_name(Path::new("/nonexistent/i2c-1")).as_deref(),
+            Some("i2c-1")
+        );
+        assert_eq!(
+            adapter_name(Path::new("/nonexistent/i2c-12")).as_deref(),
+            Some("i2c-12")
+        );
+        assert_eq!(adapter_name(Path::new("/nonexistent/i2c-")), None);
+        assert_eq!(adapter_name(Path::new("/nonexistent/i2c-1a")), None);
+        assert_eq!(adapter_name(Path::new("/nonexistent/spidev0.0")), None);
+    }
+
+    #[test]
+    fn test_parse_clock_frequency() {
+        assert_eq!(
+            parse_clock_frequency(&400_000u32.to_be_bytes()),
+            Some(400_000)
+        );
+        assert_eq!(parse_clock_frequency(&[0x00, 0x01, 0x86]), None);
+        assert_eq!(parse_clock_frequency(&[]), None);
+    }
+
+    #[test]
+    fn test_quick_command() {
+        use embedded_hal::i2c::Operation;
+
+        let mut empty = [];
+        let mut one = [0];
+        assert_eq!(quick_command(&[Operation::Read(&mut empty)]), Some(true));
+        assert_eq!(quick_command(&[Operation::Write(&[])]), Some(false));
+        assert_eq!(quick_command(&[Operation::Read(&mut one)]), None);
+        assert_eq!(quick_command(&[Operation::Write(&[1])]), None);
+        assert_eq!(
+            quick_command(&[Operation::Write(&[]), Operation::Write(&[])]),
+            None
+        );
+        assert_eq!(quick_command(&[]), None);
+    }
+
+    /// Device recording the SMBus calls made, answering reads with incrementing bytes, and
+    /// block reads with at most `.1` bytes if set
+    #[derive(Default)]
+    struct MockSmbus(Vec<String>, Option<u8>);
+
+    impl i2cdev::core::I2CDevice for MockSmbus {
+        type Error = LinuxI2CError;
+
+        fn read(&mut self, _data: &mut [u8]) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn write(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn smbus_write_quick(&mut self, bit: bool) -> Result<(), Self::Error> {
+            self.0.push(format!("quick {}", bit));
+            Ok(())
+        }
+
+        fn smbus_read_byte(&mut self) -> Result<u8, Self::Error> {
+            self.0.push("read byte".into());
+            Ok(1)
+        }
+
+        fn smbus_write_byte(&mut self, value: u8) -> Result<(), Self::Error> {
+            self.0.push(format!("write byte {}", value));
+            Ok(())
+        }
+
+        fn smbus_read_byte_data(&mut self, register: u8) -> Result<u8, Self::Error> {
+            self.0.push(format!("read byte data {}", register));
+            Ok(1)
+        }
+
+        fn smbus_write_byte_data(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+            self.0
+                .push(format!("write byte data {} {}", register, value));
+            Ok(())
+        }
+
+        fn smbus_read_word_data(&mut self, register: u8) -> Result<u16, Self::Error> {
+            self.0.push(format!("read word data {}", register));
+            Ok(0x0201)
+        }
+
+        fn smbus_write_word_data(&mut self, register: u8, value: u16) -> Result<(), Self::Error> {
+            self.0
+                .push(format!("write word data {} {:#06x}", register, value));
+            Ok(())
+        }
+
+        fn smbus_read_block_data(&mut self, _register: u8) -> Result<Vec<u8>, Self::Error> {
+            unimplemented!()
+        }
+
+        fn smbus_read_i2c_block_data(
+            &mut self,
+            register: u8,
+            len: u8,
+        ) -> Result<Vec<u8>, Self::Error> {
+            self.0.push(format!("read block {} {}", register, len));
+            Ok((1..=self.1.unwrap_or(len).min(len)).collect())
+        }
+
+        fn smbus_write_block_data(
+            &mut self,
+            _register: u8,
+            _values: &[u8],
+        ) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn smbus_write_i2c_block_data(
+            &mut self,
+            register: u8,
+            values: &[u8],
+        ) -> Result<(), Self::Error> {
+            self.0
+                .push(format!("write block {} {:?}", register, values));
+            Ok(())
+        }
+
+        fn smbus_process_block(
+            &mut self,
+            _register: u8,
+            _values: &[u8],
+        ) -> Result<Vec<u8>, Self::Error> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_smbus_transaction() {
+        use embedded_hal::i2c::Operation::{self, Read, Write};
+
+        let mut dev = MockSmbus::default();
+        let (mut one, mut two, mut four) = ([0; 1], [0; 2], [0; 4]);
+        let mut run =
+            |ops: &mut [Operation<'_>]| smbus_transaction(&mut dev, ops).map(Result::unwrap);
+        assert!(run(&mut [Read(&mut [])]).is_some());
+        assert!(run(&mut [Write(&[])]).is_some());
+        assert!(run(&mut [Read(&mut one)]).is_some());
+        assert!(run(&mut [Write(&[7])]).is_some());
+        assert!(run(&mut [Write(&[7, 8])]).is_some());
+        assert!(run(&mut [Write(&[7, 8, 9])]).is_some());
+        assert!(run(&mut [Write(&[7, 8, 9, 10])]).is_some());
+        assert!(run(&mut [Write(&[7]), Read(&mut one)]).is_some());
+        assert!(run(&mut [Write(&[7]), Read(&mut two)]).is_some());
+        assert!(run(&mut [Write(&[7]), Read(&mut four)]).is_some());
+        assert_eq!((one, two, four), ([1], [1, 2], [1, 2, 3, 4]));
+
+        assert!(run(&mut [Read(&mut two)]).is_none());
+        assert!(run(&mut [Write(&[7, 8]), Read(&mut two)]).is_none());
+        assert!(run(&mut [Write(&[7]), Read(&mut [0; 33])]).is_none());
+        assert!(run(&mut [Write(&[7]), Read(&mut [])]).is_none());
+        assert!(run(&mut [Write(&[7]), Read(&mut one), Read(&mut two)]).is_none());
+        assert!(run(&mut []).is_none());
+
+        assert_eq!(
+            dev.0,
+            [
+                "quick true",
+                "quick false",
+                "read byte",
+                "write byte 7",
+                "write byte data 7 8",
+                "write word data 7 0x0908",
+                "write block 7 [8, 9, 10]",
+                "read byte data 7",
+                "read word data 7",
+                "read block 7 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_smbus_transaction_short_block_read() {
+        use embedded_hal::i2c::Operation::{Read, Write};
+
+        let mut dev = MockSmbus(Vec::new(), Some(3));
+        let mut buf = [0; 4];
+        match smbus_transaction(&mut dev, &mut [Write(&[7]), Read(&mut buf)]) {
+            Some(Err(LinuxI2CError::Io(err))) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+                assert_eq!(err.to_string(), "SMBus block read returned 3 of 4 bytes");
+            }
+            _ => panic!("expected an UnexpectedEof error"),
+        }
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[test]
+    fn test_check_message_count() {
+        assert!(check_message_count(0).is_ok());
+        assert!(check_message_count(MAX_MESSAGES).is_ok());
+        match check_message_count(MAX_MESSAGES + 1) {
+            Err(LinuxI2CError::Io(err)) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput)
+            }
+            _ => panic!("expected an InvalidInput error"),
+        }
+    }
+
+    #[test]
+    fn test_message_flags() {
+        use i2cdev::linux::I2CMessageFlags;
+
+        let bits = |read, ten_bit| message_flags(read, ten_bit).bits();
+        let (read, ten) = (I2CMessageFlags::READ, I2CMessageFlags::TEN_BIT_ADDRESS);
+        assert_eq!(bits(false, false), 0);
+        assert_eq!(bits(true, false), read.bits());
+        assert_eq!(bits(false, true), ten.bits());
+        assert_eq!(bits(true, true), (read | ten).bits());
+    }
+
+    #[test]
+    fn test_transferred_lengths() {
+        use embedded_hal::i2c::Operation;
+
+        let mut read = [0; 4];
+        let ops = [Operation::Write(&[1, 2]), Operation::Read(&mut read)];
+        assert_eq!(transferred_lengths(&ops, 2), [2, 4]);
+        assert_eq!(transferred_lengths(&ops, 1), [2, 0]);
+        assert_eq!(transferred_lengths(&ops, 0), [0, 0]);
+    }
+
+    #[test]
+    fn test_is_missing() {
+        let nxio = || LinuxI2CError::Io(std::io::Error::from_raw_os_error(Errno::ENXIO as i32));
+        assert!(is_missing(
+            &LinuxI2CError::Errno(Errno::ENODEV as i32),
+            || { panic!("ENODEV needs no check of the device node") }
+        ));
+        assert!(is_missing(&nxio(), || true));
+        assert!(!is_missing(
+            &LinuxI2CError::Errno(Errno::EIO as i32),
+            || true
+        ));
+        assert!(!is_missing(
+            &LinuxI2CError::Io(std::io::Error::from(std::io::ErrorKind::Other)),
+            || true
+        ));
+    }
+
+    #[test]
+    fn test_nack_not_retried() {
+        // A NACK gives ENXIO too, but the device node is still there
+        let nack = LinuxI2CError::Errno(Errno::ENXIO as i32);
+        let node = std::env::temp_dir();
+        assert!(!is_missing(&nack, || !node.exists()));
+        assert_eq!(
+            kind(Errno::ENXIO),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+    }
+
+    #[test]
+    fn test_error_kind_io() {
+        let err = std::io::Error::from_raw_os_error(Errno::EREMOTEIO as i32);
+        assert_eq!(
+            I2CError::from(LinuxI2CError::Io(err)).kind(),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err = I2CError::from(LinuxI2CError::Errno(Errno::ENXIO as i32));
+        assert_eq!(
+            std::io::Error::from(err).raw_os_error(),
+            Some(Errno::ENXIO as i32)
+        );
+
+        let err = std::io::Error::new(std::io::ErrorKind::InvalidInput, "too long");
+        let err = std::io::Error::from(I2CError::from(LinuxI2CError::Io(err)));
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "too long");
+    }
+}
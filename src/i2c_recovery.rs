@@ -0,0 +1,171 @@
+//! Recovery of a stuck I2C bus by bit-banging [`embedded-hal`] digital pins
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use crate::Delay;
+
+/// Largest number of clock pulses needed for a device to finish the byte it is sending
+const MAX_PULSES: usize = 9;
+
+/// Half of an SCL period at 100 kHz, in microseconds
+const HALF_PERIOD_US: u32 = 5;
+
+/// Free an I2C bus whose SDA line is held low by a device, and return whether SDA was
+/// released.
+///
+/// A device interrupted in the middle of sending a byte, e.g. by a reset of the controller
+/// or of the program driving it, keeps holding SDA low, waiting for clock pulses that never
+/// come, which blocks every further transaction. This clocks SCL until the device releases
+/// SDA, at most nine times, one per remaining bit and the acknowledge, and then sends a stop
+/// condition so that all devices go back to idle. The bus is clocked at roughly 100 kHz or
+/// slower, as the half periods are [`Delay`]s.
+///
+/// The bus adapter must not be using the lines in the meantime, so this is typically done
+/// with the SCL and SDA pins temporarily requested as GPIOs, e.g. as [`CdevPin`]s, after
+/// unbinding the adapter driver or before loading it. Both pins must be open-drain outputs,
+/// so that driving them high releases the line to its pull-up, and SDA must also be
+/// readable. Returns `false` if SDA is still low after the stop condition.
+///
+/// [`CdevPin`]: https://docs.rs/linux-embedded-hal/latest/linux_embedded_hal/struct.CdevPin.html
+pub fn recover_i2c_bus<SCL, SDA, E>(scl: &mut SCL, sda: &mut SDA) -> Result<bool, E>
+where
+    SCL: OutputPin<Error = E>,
+    SDA: InputPin<Error = E> + OutputPin<Error = E>,
+{
+    let mut delay = Delay;
+    sda.set_high()?;
+    scl.set_high()?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    for _ in 0..MAX_PULSES {
+        if sda.is_high()? {
+            break;
+        }
+        scl.set_low()?;
+        delay.delay_us(HALF_PERIOD_US);
+        scl.set_high()?;
+        delay.delay_us(HALF_PERIOD_US);
+    }
+
+    // Stop condition: SDA rising while SCL is high
+    scl.set_low()?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_low()?;
+    delay.delay_us(HALF_PERIOD_US);
+    scl.set_high()?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_high()?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    sda.is_high()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+    use std::cell::RefCell;
+
+    /// Bus with a device holding SDA low for a number of SCL falling edges
+    struct Bus {
+        scl: bool,
+        sda_driven: bool,
+        stuck_edges: usize,
+        edges: usize,
+        stops: usize,
+    }
+
+    impl Bus {
+        fn new(stuck_edges: usize) -> RefCell<Self> {
+            RefCell::new(Bus {
+                scl: true,
+                sda_driven: true,
+                stuck_edges,
+                edges: 0,
+                stops: 0,
+            })
+        }
+
+        /// Whether the device released SDA
+        fn sda_released(&self) -> bool {
+            self.edges >= self.stuck_edges
+        }
+
+        fn sda(&self) -> bool {
+            self.sda_driven && self.sda_released()
+        }
+    }
+
+    struct Scl<'a>(&'a RefCell<Bus>);
+    struct Sda<'a>(&'a RefCell<Bus>);
+
+    impl ErrorType for Scl<'_> {
+        type Error = Infallible;
+    }
+
+    impl ErrorType for Sda<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Scl<'_> {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            let mut bus = self.0.borrow_mut();
+            if bus.scl {
+                bus.edges += 1;
+            }
+            bus.scl = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().scl = true;
+            Ok(())
+        }
+    }
+
+    impl OutputPin for Sda<'_> {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().sda_driven = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            let mut bus = self.0.borrow_mut();
+            if bus.scl && !bus.sda_driven && bus.sda_released() {
+                bus.stops += 1;
+            }
+            bus.sda_driven = true;
+            Ok(())
+        }
+    }
+
+    impl InputPin for Sda<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.0.borrow().sda())
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.0.borrow().sda())
+        }
+    }
+
+    #[test]
+    fn test_recover_i2c_bus() {
+        for stuck_edges in [0, 1, 9] {
+            let bus = Bus::new(stuck_edges);
+            assert_eq!(recover_i2c_bus(&mut Scl(&bus), &mut Sda(&bus)), Ok(true));
+            let bus = bus.borrow();
+            // One more falling edge for the stop condition
+            assert_eq!(bus.edges, stuck_edges + 1);
+            assert_eq!(bus.stops, 1);
+        }
+
+        let bus = Bus::new(20);
+        assert_eq!(recover_i2c_bus(&mut Scl(&bus), &mut Sda(&bus)), Ok(false));
+        assert_eq!(bus.borrow().edges, MAX_PULSES + 1);
+    }
+}
@@ -0,0 +1,149 @@
+//! Software logic inversion for [`embedded-hal`] digital pins
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, PinState, StatefulOutputPin};
+
+/// Wrapper around any digital pin that inverts its logic in software
+///
+/// `set_high` drives the inner pin low and vice versa, and `is_high` reports whether the inner
+/// pin is low. This is useful when a line turns out to be wired active-low but was requested
+/// without the active-low flag, or when the underlying pin type has no such flag at all.
+///
+/// Each trait is only implemented when the inner pin implements it.
+pub struct InvertedPin<P>(pub P);
+
+impl<P> InvertedPin<P> {
+    /// Wrap `pin`, inverting its logic
+    pub fn new(pin: P) -> Self {
+        InvertedPin(pin)
+    }
+
+    /// Release the inner pin
+    pub fn into_inner(self) -> P {
+        self.0
+    }
+}
+
+impl<P: ErrorType> ErrorType for InvertedPin<P> {
+    type Error = P::Error;
+}
+
+impl<P: OutputPin> OutputPin for InvertedPin<P> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
+    }
+
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        self.0.set_state(!state)
+    }
+}
+
+impl<P: StatefulOutputPin> StatefulOutputPin for InvertedPin<P> {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_set_low()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_set_high()
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.0.toggle()
+    }
+}
+
+impl<P: InputPin> InputPin for InvertedPin<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_low()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_high()
+    }
+}
+
+impl<P> core::ops::Deref for InvertedPin<P> {
+    type Target = P;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<P> core::ops::DerefMut for InvertedPin<P> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    struct MockPin(bool);
+
+    impl ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0 = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0 = true;
+            Ok(())
+        }
+    }
+
+    impl StatefulOutputPin for MockPin {
+        fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.0)
+        }
+
+        fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.0)
+        }
+    }
+
+    impl InputPin for MockPin {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.0)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.0)
+        }
+    }
+
+    #[test]
+    fn test_output_inverted() {
+        let mut pin = InvertedPin::new(MockPin(false));
+        pin.set_high().unwrap();
+        assert!(!pin.0 .0);
+        pin.set_low().unwrap();
+        assert!(pin.0 .0);
+        pin.set_state(PinState::High).unwrap();
+        assert!(!pin.0 .0);
+        assert!(pin.is_set_high().unwrap());
+        pin.toggle().unwrap();
+        assert!(pin.is_set_low().unwrap());
+        assert!(pin.0 .0);
+    }
+
+    #[test]
+    fn test_input_inverted() {
+        let mut pin = InvertedPin::new(MockPin(true));
+        assert!(pin.is_low().unwrap());
+        assert!(!pin.is_high().unwrap());
+        assert!(pin.into_inner().0);
+    }
+}
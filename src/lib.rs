@@ -15,6 +15,7 @@
 #[cfg(feature = "i2c")]
 pub use i2cdev;
 pub use nb;
+#[cfg(feature = "serialport_reexport")]
 pub use serialport;
 #[cfg(feature = "spi")]
 pub use spidev;
@@ -32,26 +33,84 @@ mod sysfs_pin;
 /// Cdev Pin wrapper module
 mod cdev_pin;
 
+#[cfg(feature = "gpio_cdev")]
+/// Cdev port wrapper module
+mod cdev_port;
+
+#[cfg(feature = "gpio_cdev")]
+/// Cdev bidirectional pin wrapper module
+mod cdev_bidir_pin;
+
 #[cfg(feature = "gpio_cdev")]
 /// Cdev pin re-export
-pub use cdev_pin::{CdevPin, CdevPinError};
+pub use cdev_pin::{CdevPin, CdevPinError, ModeChangeError};
+
+#[cfg(all(feature = "gpio_cdev", feature = "async-tokio"))]
+/// Cdev debounced input re-export
+pub use cdev_pin::CdevDebouncedInput;
+
+#[cfg(feature = "gpio_cdev")]
+/// Cdev bidirectional pin re-export
+pub use cdev_bidir_pin::CdevBidirPin;
+
+#[cfg(feature = "gpio_cdev")]
+/// Cdev port re-export
+pub use cdev_port::{CdevInputPort, CdevOutputPort};
+
+#[cfg(all(feature = "gpio_cdev", feature = "async-tokio"))]
+/// Cdev input group re-export
+pub use cdev_port::CdevInputGroup;
 
 #[cfg(feature = "gpio_sysfs")]
 /// Sysfs pin re-export
 pub use sysfs_pin::{SysfsPin, SysfsPinError};
 
+mod bitbang_i2c;
 mod delay;
+#[cfg(any(feature = "i2c", feature = "spi", feature = "gpio_cdev"))]
+mod discovery;
 #[cfg(feature = "i2c")]
 mod i2c;
+mod i2c_recovery;
+mod inverted_pin;
+#[cfg(feature = "spi_recording")]
+mod recording;
+mod retry;
+#[cfg(feature = "serial")]
 mod serial;
+mod shared_i2c;
+mod soft_pwm;
 #[cfg(feature = "spi")]
 mod spi;
 mod timer;
 
-pub use crate::delay::Delay;
+pub use crate::bitbang_i2c::{BitBangI2c, BitBangI2cError};
+#[cfg(feature = "async-tokio")]
+pub use crate::delay::CooperativeDelay;
+pub use crate::delay::{Delay, DelayCalibration, NoopDelay, SpinDelay};
+#[cfg(feature = "i2c")]
+pub use crate::discovery::list_i2c_adapters;
+#[cfg(feature = "spi")]
+pub use crate::discovery::list_spi_devices;
+#[cfg(any(feature = "i2c", feature = "spi", feature = "gpio_cdev"))]
+pub use crate::discovery::BusDevice;
+#[cfg(feature = "gpio_cdev")]
+pub use crate::discovery::{list_gpiochips, sysfs_to_cdev, GpioChipInfo};
 #[cfg(feature = "i2c")]
 pub use crate::i2c::{I2CError, I2cdev};
-pub use crate::serial::{Serial, SerialError};
+pub use crate::i2c_recovery::recover_i2c_bus;
+pub use crate::inverted_pin::InvertedPin;
+#[cfg(feature = "spi_recording")]
+pub use crate::recording::{Loopback, RecordedOperation, RecordingSpi};
+pub use crate::retry::Retry;
+#[cfg(feature = "serial")]
+pub use crate::serial::{
+    DataBits, FlowControl, Parity, Serial, SerialBuilder, SerialError, SerialSettings, StopBits,
+};
+pub use crate::shared_i2c::{MutexI2c, MutexI2cDevice, RefCellI2c, RefCellI2cDevice};
+pub use crate::soft_pwm::{SoftPwm, SoftPwmError};
 #[cfg(feature = "spi")]
-pub use crate::spi::{SPIError, SpidevBus, SpidevDevice};
-pub use crate::timer::{CountDown, Periodic, SysTimer};
+pub use crate::spi::{CsGuard, CsGuardError, SPIError, SpiConfig, SpidevBus, SpidevDevice};
+#[cfg(feature = "async-tokio")]
+pub use crate::timer::AsyncSysTimer;
+pub use crate::timer::{BlockingCountDown, CountDown, OneShotTimer, Periodic, SysTimer};
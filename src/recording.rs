@@ -0,0 +1,320 @@
+//! Recording of [`embedded-hal`] SPI traffic for golden testing
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use core::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Operation, SpiBus, SpiDevice};
+
+/// An SPI operation recorded by [`RecordingSpi`]
+///
+/// Read buffers hold the words received once the operation completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedOperation<Word> {
+    /// Words read
+    Read(Vec<Word>),
+    /// Words written
+    Write(Vec<Word>),
+    /// Full-duplex transfer, with the words written and read
+    Transfer {
+        /// Words written
+        write: Vec<Word>,
+        /// Words read
+        read: Vec<Word>,
+    },
+    /// In-place full-duplex transfer, with the words written and read
+    TransferInPlace {
+        /// Words written
+        write: Vec<Word>,
+        /// Words read
+        read: Vec<Word>,
+    },
+    /// Delay, in nanoseconds
+    DelayNs(u32),
+    /// Flush of the bus
+    Flush,
+    /// [`SpiDevice`] transaction, holding the operations performed with CS asserted
+    Transaction(Vec<RecordedOperation<Word>>),
+}
+
+/// Wrapper around an SPI bus or device recording every operation into an in-memory transcript
+///
+/// Operations are forwarded to the inner `T`, which can be a real device such as
+/// [`SpidevDevice`](crate::SpidevDevice), or [`Loopback`] when no hardware is involved.
+/// Only operations that succeed are recorded. [`SpiBus`] methods record one entry each,
+/// while [`SpiDevice`] transactions record a single [`RecordedOperation::Transaction`] entry.
+///
+/// ```
+/// use embedded_hal::spi::SpiDevice;
+/// use linux_embedded_hal::{Loopback, RecordedOperation, RecordingSpi};
+///
+/// let mut spi = RecordingSpi::new(Loopback);
+/// spi.write(&[0x9F_u8]).unwrap();
+/// assert_eq!(
+///     spi.transcript(),
+///     [RecordedOperation::Transaction(vec![RecordedOperation::Write(vec![0x9F])])]
+/// );
+/// ```
+pub struct RecordingSpi<T, Word = u8> {
+    inner: T,
+    transcript: Vec<RecordedOperation<Word>>,
+}
+
+impl<T, Word> RecordingSpi<T, Word> {
+    /// Wrap `inner`, starting with an empty transcript
+    pub fn new(inner: T) -> Self {
+        RecordingSpi {
+            inner,
+            transcript: Vec::new(),
+        }
+    }
+
+    /// Operations recorded so far
+    pub fn transcript(&self) -> &[RecordedOperation<Word>] {
+        &self.transcript
+    }
+
+    /// Take the operations recorded so far, leaving the transcript empty
+    pub fn take_transcript(&mut self) -> Vec<RecordedOperation<Word>> {
+        std::mem::take(&mut self.transcript)
+    }
+
+    /// Release the inner bus or device
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, Word> core::ops::Deref for RecordingSpi<T, Word> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T, Word> core::ops::DerefMut for RecordingSpi<T, Word> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T: ErrorType, Word> ErrorType for RecordingSpi<T, Word> {
+    type Error = T::Error;
+}
+
+impl<T, Word> SpiBus<Word> for RecordingSpi<T, Word>
+where
+    T: SpiBus<Word>,
+    Word: Copy + 'static,
+{
+    fn read(&mut self, words: &mut [Word]) -> Result<(), Self::Error> {
+        self.inner.read(words)?;
+        self.transcript
+            .push(RecordedOperation::Read(words.to_vec()));
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[Word]) -> Result<(), Self::Error> {
+        self.inner.write(words)?;
+        self.transcript
+            .push(RecordedOperation::Write(words.to_vec()));
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [Word], write: &[Word]) -> Result<(), Self::Error> {
+        self.inner.transfer(read, write)?;
+        self.transcript.push(RecordedOperation::Transfer {
+            write: write.to_vec(),
+            read: read.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [Word]) -> Result<(), Self::Error> {
+        let write = words.to_vec();
+        self.inner.transfer_in_place(words)?;
+        self.transcript.push(RecordedOperation::TransferInPlace {
+            write,
+            read: words.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()?;
+        self.transcript.push(RecordedOperation::Flush);
+        Ok(())
+    }
+}
+
+impl<T, Word> SpiDevice<Word> for RecordingSpi<T, Word>
+where
+    T: SpiDevice<Word>,
+    Word: Copy + 'static,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, Word>]) -> Result<(), Self::Error> {
+        // Written data of in-place transfers is overwritten by the transaction,
+        // so it has to be captured beforehand.
+        let in_place_writes: Vec<Vec<Word>> = operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::TransferInPlace(buf) => Some(buf.to_vec()),
+                _ => None,
+            })
+            .collect();
+        self.inner.transaction(operations)?;
+
+        let mut in_place_writes = in_place_writes.into_iter();
+        let recorded = operations
+            .iter()
+            .map(|op| match op {
+                Operation::Read(buf) => RecordedOperation::Read(buf.to_vec()),
+                Operation::Write(buf) => RecordedOperation::Write(buf.to_vec()),
+                Operation::Transfer(read, write) => RecordedOperation::Transfer {
+                    write: write.to_vec(),
+                    read: read.to_vec(),
+                },
+                Operation::TransferInPlace(buf) => RecordedOperation::TransferInPlace {
+                    write: in_place_writes.next().unwrap_or_default(),
+                    read: buf.to_vec(),
+                },
+                Operation::DelayNs(ns) => RecordedOperation::DelayNs(*ns),
+            })
+            .collect();
+        self.transcript
+            .push(RecordedOperation::Transaction(recorded));
+        Ok(())
+    }
+}
+
+/// SPI bus and device with MOSI connected to MISO, and no hardware behind it
+///
+/// Every word written is read back in the same position, and words clocked in without a
+/// corresponding written word read as the default value. This is meant to be wrapped in a
+/// [`RecordingSpi`] to test drivers without hardware.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Loopback;
+
+impl Loopback {
+    fn loop_back<Word: Copy + Default>(read: &mut [Word], write: &[Word]) {
+        for (i, word) in read.iter_mut().enumerate() {
+            *word = write.get(i).copied().unwrap_or_default();
+        }
+    }
+}
+
+impl ErrorType for Loopback {
+    type Error = Infallible;
+}
+
+impl<Word: Copy + Default + 'static> SpiBus<Word> for Loopback {
+    fn read(&mut self, words: &mut [Word]) -> Result<(), Self::Error> {
+        Loopback::loop_back(words, &[]);
+        Ok(())
+    }
+
+    fn write(&mut self, _words: &[Word]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [Word], write: &[Word]) -> Result<(), Self::Error> {
+        Loopback::loop_back(read, write);
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, _words: &mut [Word]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<Word: Copy + Default + 'static> SpiDevice<Word> for Loopback {
+    fn transaction(&mut self, operations: &mut [Operation<'_, Word>]) -> Result<(), Self::Error> {
+        for op in operations {
+            match op {
+                Operation::Read(buf) => SpiBus::read(self, buf)?,
+                Operation::Write(buf) => SpiBus::write(self, buf)?,
+                Operation::Transfer(read, write) => SpiBus::transfer(self, read, write)?,
+                Operation::TransferInPlace(buf) => SpiBus::transfer_in_place(self, buf)?,
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RecordedOperation::*;
+
+    #[test]
+    fn test_record_bus() {
+        let mut spi = RecordingSpi::new(Loopback);
+        let mut read = [0xAA; 3];
+        SpiBus::write(&mut spi, &[1, 2]).unwrap();
+        SpiBus::transfer(&mut spi, &mut read, &[3, 4]).unwrap();
+        let mut in_place = [5, 6];
+        SpiBus::transfer_in_place(&mut spi, &mut in_place).unwrap();
+        SpiBus::read(&mut spi, &mut read[..1]).unwrap();
+        SpiBus::flush(&mut spi).unwrap();
+        assert_eq!(read, [0, 4, 0]);
+        assert_eq!(
+            spi.take_transcript(),
+            [
+                Write(vec![1, 2]),
+                Transfer {
+                    write: vec![3, 4],
+                    read: vec![3, 4, 0]
+                },
+                TransferInPlace {
+                    write: vec![5, 6],
+                    read: vec![5, 6]
+                },
+                Read(vec![0]),
+                Flush,
+            ]
+        );
+        assert!(spi.transcript().is_empty());
+    }
+
+    #[test]
+    fn test_record_device() {
+        let mut spi = RecordingSpi::new(Loopback);
+        let mut read = [0xAA; 2];
+        let mut in_place = [7];
+        SpiDevice::transaction(
+            &mut spi,
+            &mut [
+                Operation::Write(&[0x9F]),
+                Operation::DelayNs(1_000),
+                Operation::Transfer(&mut read, &[1]),
+                Operation::TransferInPlace(&mut in_place),
+            ],
+        )
+        .unwrap();
+        SpiDevice::write(&mut spi, &[0x06]).unwrap();
+        assert_eq!(
+            spi.transcript(),
+            [
+                Transaction(vec![
+                    Write(vec![0x9F]),
+                    DelayNs(1_000),
+                    Transfer {
+                        write: vec![1],
+                        read: vec![1, 0]
+                    },
+                    TransferInPlace {
+                        write: vec![7],
+                        read: vec![7]
+                    },
+                ]),
+                Transaction(vec![Write(vec![0x06])]),
+            ]
+        );
+    }
+}
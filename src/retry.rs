@@ -0,0 +1,275 @@
+//! Retrying of transient bus errors for [`embedded-hal`] bus traits
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use std::convert::TryInto;
+use std::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{self, AddressMode, I2c};
+use embedded_hal::spi::{self, SpiDevice};
+
+use crate::Delay;
+
+/// Wrapper around an I2C or SPI device that retries operations failing with a transient error
+///
+/// `K` is the error kind type of the wrapped bus, [`embedded_hal::i2c::ErrorKind`] or
+/// [`embedded_hal::spi::ErrorKind`], and selects which trait the wrapper implements. When an
+/// operation fails with an error whose kind is in the configured transient set, it is retried
+/// up to the configured number of times, optionally sleeping for a backoff delay before each
+/// retry. Any other error, or the last transient one, is returned as is.
+///
+/// ```no_run
+/// # #[cfg(feature = "i2c")] {
+/// use embedded_hal::i2c::{ErrorKind, I2c};
+/// use linux_embedded_hal::{I2cdev, Retry};
+///
+/// let dev = I2cdev::new("/dev/i2c-1").unwrap();
+/// let mut dev = Retry::new(dev, [ErrorKind::ArbitrationLoss], 3)
+///     .with_backoff(std::time::Duration::from_millis(1));
+/// dev.write(0x12u8, &[0xAB]).unwrap();
+/// # }
+/// ```
+///
+/// Note that a retried SPI transaction is performed again from the start. Buffers of
+/// `TransferInPlace` operations may already have been overwritten by the failed attempt, so
+/// the retry sends the received data rather than the original.
+pub struct Retry<T, K> {
+    inner: T,
+    transient: Vec<K>,
+    retries: usize,
+    backoff: Option<Duration>,
+}
+
+impl<T, K: PartialEq> Retry<T, K> {
+    /// Wrap `inner`, retrying up to `retries` times on errors of the `transient` kinds
+    pub fn new(inner: T, transient: impl Into<Vec<K>>, retries: usize) -> Self {
+        Retry {
+            inner,
+            transient: transient.into(),
+            retries,
+            backoff: None,
+        }
+    }
+
+    /// Sleep for `backoff` before each retry
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Release the wrapped device
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn run<R, E>(
+        &mut self,
+        kind: impl Fn(&E) -> K,
+        mut f: impl FnMut(&mut T) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let mut retries = self.retries;
+        loop {
+            match f(&mut self.inner) {
+                Err(e) if retries > 0 && self.transient.contains(&kind(&e)) => {
+                    retries -= 1;
+                    if let Some(backoff) = self.backoff {
+                        Delay.delay_us(backoff.as_micros().try_into().unwrap_or(u32::MAX));
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T, K> core::ops::Deref for Retry<T, K> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T, K> core::ops::DerefMut for Retry<T, K> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T: i2c::ErrorType> i2c::ErrorType for Retry<T, i2c::ErrorKind> {
+    type Error = T::Error;
+}
+
+impl<A, T> I2c<A> for Retry<T, i2c::ErrorKind>
+where
+    A: AddressMode + Copy,
+    T: I2c<A>,
+{
+    fn read(&mut self, address: A, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(i2c::Error::kind, |i2c| i2c.read(address, read))
+    }
+
+    fn write(&mut self, address: A, write: &[u8]) -> Result<(), Self::Error> {
+        self.run(i2c::Error::kind, |i2c| i2c.write(address, write))
+    }
+
+    fn write_read(&mut self, address: A, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(i2c::Error::kind, |i2c| i2c.write_read(address, write, read))
+    }
+
+    fn transaction(
+        &mut self,
+        address: A,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run(i2c::Error::kind, |i2c| i2c.transaction(address, operations))
+    }
+}
+
+impl<T: spi::ErrorType> spi::ErrorType for Retry<T, spi::ErrorKind> {
+    type Error = T::Error;
+}
+
+impl<Word, T> SpiDevice<Word> for Retry<T, spi::ErrorKind>
+where
+    Word: Copy + 'static,
+    T: SpiDevice<Word>,
+{
+    fn transaction(
+        &mut self,
+        operations: &mut [spi::Operation<'_, Word>],
+    ) -> Result<(), Self::Error> {
+        self.run(spi::Error::kind, |spi| spi.transaction(operations))
+    }
+
+    fn read(&mut self, buf: &mut [Word]) -> Result<(), Self::Error> {
+        self.run(spi::Error::kind, |spi| spi.read(buf))
+    }
+
+    fn write(&mut self, buf: &[Word]) -> Result<(), Self::Error> {
+        self.run(spi::Error::kind, |spi| spi.write(buf))
+    }
+
+    fn transfer(&mut self, read: &mut [Word], write: &[Word]) -> Result<(), Self::Error> {
+        self.run(spi::Error::kind, |spi| spi.transfer(read, write))
+    }
+
+    fn transfer_in_place(&mut self, buf: &mut [Word]) -> Result<(), Self::Error> {
+        self.run(spi::Error::kind, |spi| spi.transfer_in_place(buf))
+    }
+}
+
+/// Error of an ioctl that a signal can interrupt
+#[cfg(any(feature = "i2c", feature = "spi"))]
+pub(crate) trait Interrupted {
+    /// Whether the ioctl failed with `EINTR`
+    fn is_interrupted(&self) -> bool;
+}
+
+#[cfg(any(feature = "i2c", feature = "spi"))]
+impl Interrupted for std::io::Error {
+    fn is_interrupted(&self) -> bool {
+        self.kind() == std::io::ErrorKind::Interrupted
+    }
+}
+
+/// Run the ioctl performed by `f`, retrying it for as long as a signal interrupts it.
+///
+/// An `I2C_RDWR` or `SPI_IOC_MESSAGE` ioctl interrupted by a signal fails with `EINTR` before
+/// any message is sent, so it is safe to issue it again. Any other error is returned as is.
+#[cfg(any(feature = "i2c", feature = "spi"))]
+pub(crate) fn retry_interrupted<T, E: Interrupted>(
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    loop {
+        match f() {
+            Err(err) if err.is_interrupted() => {}
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::i2c::{ErrorKind, Operation};
+
+    #[derive(Debug)]
+    struct MockError(ErrorKind);
+
+    impl i2c::Error for MockError {
+        fn kind(&self) -> ErrorKind {
+            self.0
+        }
+    }
+
+    /// I2C bus failing its first `failures` transactions with `kind`
+    struct FlakyI2c {
+        failures: usize,
+        kind: ErrorKind,
+        attempts: usize,
+    }
+
+    impl FlakyI2c {
+        fn new(failures: usize, kind: ErrorKind) -> Self {
+            FlakyI2c {
+                failures,
+                kind,
+                attempts: 0,
+            }
+        }
+    }
+
+    impl i2c::ErrorType for FlakyI2c {
+        type Error = MockError;
+    }
+
+    impl I2c for FlakyI2c {
+        fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.attempts += 1;
+            if self.attempts <= self.failures {
+                return Err(MockError(self.kind));
+            }
+            for op in operations {
+                if let Operation::Read(buf) = op {
+                    buf.fill(0x42);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recovers_after_retries() {
+        let flaky = FlakyI2c::new(2, ErrorKind::ArbitrationLoss);
+        let mut dev = Retry::new(flaky, [ErrorKind::ArbitrationLoss], 2)
+            .with_backoff(Duration::from_micros(10));
+        let mut buf = [0; 2];
+        dev.write_read(0x12, &[0xAB], &mut buf).unwrap();
+        assert_eq!(buf, [0x42, 0x42]);
+        assert_eq!(dev.attempts, 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let flaky = FlakyI2c::new(3, ErrorKind::ArbitrationLoss);
+        let mut dev = Retry::new(flaky, [ErrorKind::ArbitrationLoss], 2);
+        let err = dev.write(0x12, &[0xAB]).unwrap_err();
+        assert_eq!(err.0, ErrorKind::ArbitrationLoss);
+        assert_eq!(dev.attempts, 3);
+    }
+
+    #[test]
+    fn test_does_not_retry_other_errors() {
+        let flaky = FlakyI2c::new(1, ErrorKind::Bus);
+        let mut dev = Retry::new(flaky, vec![ErrorKind::ArbitrationLoss], 5);
+        let err = dev.write(0x12, &[0xAB]).unwrap_err();
+        assert_eq!(err.0, ErrorKind::Bus);
+        assert_eq!(dev.into_inner().attempts, 1);
+    }
+}
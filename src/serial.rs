@@ -2,9 +2,11 @@
 //!
 //! [`embedded-hal`]: https://docs.rs/embedded-hal
 
-use serialport::{SerialPortBuilder, TTYPort};
+use serialport::{SerialPort, SerialPortBuilder, TTYPort};
 use std::fmt;
 use std::io::{ErrorKind as IoErrorKind, Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd};
+use std::time::Duration;
 
 /// Newtype around [`serialport::TTYPort`] that implements
 /// the `embedded-hal` traits.
@@ -20,6 +22,407 @@ impl Serial {
     pub fn open_from_builder(builder: SerialPortBuilder) -> Result<Serial, serialport::Error> {
         Ok(Serial(builder.open_native()?))
     }
+
+    /// Wrap an already open tty file descriptor, such as one inherited from a parent process
+    /// or passed by systemd, without reopening the port
+    ///
+    /// The line settings (baud rate, framing, flow control) are taken as-is from the
+    /// descriptor until reconfigured through the inner [`TTYPort`]. As for [`Serial::open`],
+    /// the read timeout is zero. Like any [`TTYPort`], the port is made exclusive with
+    /// `TIOCEXCL` and `flock`, when possible.
+    ///
+    /// The descriptor must refer to a terminal device. It is owned by the returned `Serial`
+    /// and closed when it is dropped.
+    pub fn from_fd(fd: OwnedFd) -> Serial {
+        // SAFETY: the descriptor is open and owned, and its ownership is transferred to the port
+        let mut port = unsafe { TTYPort::from_raw_fd(fd.into_raw_fd()) };
+        // Only sets a field, this cannot fail
+        let _ = port.set_timeout(Duration::ZERO);
+        Serial(port)
+    }
+
+    /// Start configuring a serial port at the given path
+    ///
+    /// The port defaults to 9600 baud, 8 data bits, no parity, one stop bit, no flow control
+    /// and a zero timeout, which suits the non-blocking `embedded-hal-nb` traits.
+    pub fn builder(path: impl Into<String>) -> SerialBuilder {
+        SerialBuilder(serialport::new(path.into(), 9600))
+    }
+
+    /// Wait until all written data has been transmitted, then close the port
+    ///
+    /// Dropping a `Serial` closes the port right away, and data still queued for
+    /// transmission at that point may be lost, e.g. the last command sent before shutting
+    /// down. This drains the output first (`tcdrain`). Note that the drain blocks until the
+    /// data has left the UART, which with hardware flow control may be forever if the peer
+    /// doesn't accept it. For that reason dropping does not drain implicitly.
+    pub fn close(mut self) -> Result<(), SerialError> {
+        loop {
+            match self.0.flush() {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+                Err(err) => return Err(SerialError { err: err.kind() }),
+            }
+        }
+    }
+
+    /// Lock the port against, or open it to, other openers
+    ///
+    /// When exclusive, further `open` calls on the tty fail with `EBUSY` (`TIOCEXCL`), except
+    /// for processes with `CAP_SYS_ADMIN`, and an exclusive `flock` is held, which is only
+    /// honoured by programs that use `flock` themselves. Handles that already have the port
+    /// open are not affected. Ports are made exclusive by default when opened.
+    ///
+    /// When not exclusive, a shared `flock` is held instead. Since `serialport` itself takes an
+    /// exclusive `flock` on open, other `serialport` users still cannot open the port then,
+    /// but any other program can.
+    ///
+    /// See [`TTYPort::set_exclusive`] for details.
+    pub fn set_exclusive(&mut self, exclusive: bool) -> Result<(), serialport::Error> {
+        self.0.set_exclusive(exclusive)
+    }
+
+    /// Whether the port is locked against other openers, see [`Serial::set_exclusive`]
+    pub fn exclusive(&self) -> bool {
+        self.0.exclusive()
+    }
+
+    /// Set the amount of time reads wait for data to arrive
+    ///
+    /// This bounds [`Serial::read_with_timeout`]. It also applies to the `embedded-hal-nb`
+    /// `read` and to [`Serial::read_available`], which wait up to this long for data before
+    /// returning `WouldBlock`, so keep it at zero (the default of [`Serial::open`]) for
+    /// purely non-blocking use.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), serialport::Error> {
+        self.0.set_timeout(timeout)
+    }
+
+    /// The amount of time reads wait for data to arrive
+    pub fn timeout(&self) -> Duration {
+        self.0.timeout()
+    }
+
+    /// Read into `buf`, blocking until data arrives or the [timeout](Serial::set_timeout)
+    /// elapses
+    ///
+    /// Returns the number of bytes read as soon as at least one is available. If the timeout
+    /// elapses first, an error whose [`inner`](SerialError::inner) kind is
+    /// [`TimedOut`](IoErrorKind::TimedOut) is returned. Unlike the `embedded-hal-nb` `read`,
+    /// which reports an elapsed timeout as `WouldBlock`, this is meant for request/response
+    /// protocols expecting an answer within a bounded time.
+    pub fn read_with_timeout(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        loop {
+            match self.0.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+                Err(err) => return Err(SerialError { err: err.kind() }),
+            }
+        }
+    }
+
+    /// Iterate over received bytes until the line stays silent for `timeout`
+    ///
+    /// Each byte is read with [`Serial::read_with_timeout`], so the iterator ends once no byte
+    /// arrives within `timeout`, which suits responses terminated by silence, such as AT
+    /// command replies. Other errors are yielded once, after which the iterator ends as well.
+    /// The port timeout is set to `timeout` while the iterator exists and restored when it is
+    /// dropped.
+    ///
+    /// Bytes are read one at a time, so none are lost when the iterator is dropped early,
+    /// e.g. at the end of a line with `take_while`.
+    pub fn bytes_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Iterator<Item = Result<u8, SerialError>> + '_ {
+        let previous = self.timeout();
+        // Only sets a field, this cannot fail
+        let _ = self.set_timeout(timeout);
+        TimedBytes {
+            serial: self,
+            previous,
+            done: false,
+        }
+    }
+
+    /// Query the line settings of the port from the tty
+    ///
+    /// This reports what the port actually runs at, which may differ from what was asked
+    /// for, e.g. when the driver rounds the baud rate or doesn't support some framing, and
+    /// is the only way to learn the settings of a port wrapped with [`Serial::from_fd`].
+    pub fn settings(&self) -> Result<SerialSettings, SerialError> {
+        Ok(SerialSettings {
+            baud_rate: self.0.baud_rate().map_err(serialport_error)?,
+            data_bits: self.0.data_bits().map_err(serialport_error)?.into(),
+            parity: self.0.parity().map_err(serialport_error)?.into(),
+            stop_bits: self.0.stop_bits().map_err(serialport_error)?.into(),
+            flow_control: self.0.flow_control().map_err(serialport_error)?.into(),
+        })
+    }
+
+    /// Read as many bytes as are immediately available into `buf`
+    ///
+    /// Returns the number of bytes read, or `WouldBlock` if none are available. This needs a
+    /// single system call for the whole slice, instead of one per byte as with the
+    /// `embedded-hal-nb` `read`, and maps errors the same way.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> nb::Result<usize, SerialError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.0.read(buf).map_err(translate_io_errors)? {
+            0 => Err(nb::Error::WouldBlock),
+            n => Ok(n),
+        }
+    }
+}
+
+/// Iterator returned by [`Serial::bytes_with_timeout`]
+struct TimedBytes<'a> {
+    serial: &'a mut Serial,
+    /// Port timeout to restore on drop
+    previous: Duration,
+    done: bool,
+}
+
+impl Iterator for TimedBytes<'_> {
+    type Item = Result<u8, SerialError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buf = [0];
+        match self.serial.read_with_timeout(&mut buf) {
+            Ok(1) => Some(Ok(buf[0])),
+            // End of file, the other end hung up
+            Ok(_) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                (*err.inner() != IoErrorKind::TimedOut).then_some(Err(err))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for TimedBytes<'_> {}
+
+impl Drop for TimedBytes<'_> {
+    fn drop(&mut self) {
+        let _ = self.serial.set_timeout(self.previous);
+    }
+}
+
+/// Builder for a [`Serial`] port, created by [`Serial::builder`]
+#[derive(Debug, Clone)]
+pub struct SerialBuilder(SerialPortBuilder);
+
+impl SerialBuilder {
+    /// Set the baud rate in symbols per second
+    pub fn baud_rate(self, baud_rate: u32) -> Self {
+        SerialBuilder(self.0.baud_rate(baud_rate))
+    }
+
+    /// Set the number of bits used to represent a character sent on the line
+    ///
+    /// Takes either a [`DataBits`] or a [`serialport::DataBits`].
+    pub fn data_bits(self, data_bits: impl Into<serialport::DataBits>) -> Self {
+        SerialBuilder(self.0.data_bits(data_bits.into()))
+    }
+
+    /// Set the type of parity to use for error checking
+    ///
+    /// Takes either a [`Parity`] or a [`serialport::Parity`].
+    pub fn parity(self, parity: impl Into<serialport::Parity>) -> Self {
+        SerialBuilder(self.0.parity(parity.into()))
+    }
+
+    /// Set the number of bits to use to signal the end of a character
+    ///
+    /// Takes either a [`StopBits`] or a [`serialport::StopBits`].
+    pub fn stop_bits(self, stop_bits: impl Into<serialport::StopBits>) -> Self {
+        SerialBuilder(self.0.stop_bits(stop_bits.into()))
+    }
+
+    /// Set the type of signalling to use for controlling data transfer
+    ///
+    /// Takes either a [`FlowControl`] or a [`serialport::FlowControl`].
+    pub fn flow_control(self, flow_control: impl Into<serialport::FlowControl>) -> Self {
+        SerialBuilder(self.0.flow_control(flow_control.into()))
+    }
+
+    /// Set the amount of time to wait to receive data before giving up
+    ///
+    /// With a non-zero timeout, the `embedded-hal-nb` `read` blocks for up to this long
+    /// before returning `WouldBlock`.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        SerialBuilder(self.0.timeout(timeout))
+    }
+
+    /// Open the configured serial port
+    pub fn open(self) -> Result<Serial, serialport::Error> {
+        Serial::open_from_builder(self.0)
+    }
+}
+
+/// Number of bits per character, see [`SerialBuilder::data_bits`]
+///
+/// Mirrors [`serialport::DataBits`], so that ports can be configured without naming types of
+/// a particular `serialport` version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataBits {
+    /// 5 bits per character
+    Five,
+    /// 6 bits per character
+    Six,
+    /// 7 bits per character
+    Seven,
+    /// 8 bits per character
+    Eight,
+}
+
+impl From<serialport::DataBits> for DataBits {
+    fn from(data_bits: serialport::DataBits) -> Self {
+        match data_bits {
+            serialport::DataBits::Five => DataBits::Five,
+            serialport::DataBits::Six => DataBits::Six,
+            serialport::DataBits::Seven => DataBits::Seven,
+            serialport::DataBits::Eight => DataBits::Eight,
+        }
+    }
+}
+
+impl From<DataBits> for serialport::DataBits {
+    fn from(data_bits: DataBits) -> Self {
+        match data_bits {
+            DataBits::Five => serialport::DataBits::Five,
+            DataBits::Six => serialport::DataBits::Six,
+            DataBits::Seven => serialport::DataBits::Seven,
+            DataBits::Eight => serialport::DataBits::Eight,
+        }
+    }
+}
+
+/// Parity checking mode, see [`SerialBuilder::parity`]
+///
+/// Mirrors [`serialport::Parity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Parity bit making the number of set bits odd
+    Odd,
+    /// Parity bit making the number of set bits even
+    Even,
+}
+
+impl From<serialport::Parity> for Parity {
+    fn from(parity: serialport::Parity) -> Self {
+        match parity {
+            serialport::Parity::None => Parity::None,
+            serialport::Parity::Odd => Parity::Odd,
+            serialport::Parity::Even => Parity::Even,
+        }
+    }
+}
+
+impl From<Parity> for serialport::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        }
+    }
+}
+
+/// Number of stop bits, see [`SerialBuilder::stop_bits`]
+///
+/// Mirrors [`serialport::StopBits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StopBits {
+    /// One stop bit
+    One,
+    /// Two stop bits
+    Two,
+}
+
+impl From<serialport::StopBits> for StopBits {
+    fn from(stop_bits: serialport::StopBits) -> Self {
+        match stop_bits {
+            serialport::StopBits::One => StopBits::One,
+            serialport::StopBits::Two => StopBits::Two,
+        }
+    }
+}
+
+impl From<StopBits> for serialport::StopBits {
+    fn from(stop_bits: StopBits) -> Self {
+        match stop_bits {
+            StopBits::One => serialport::StopBits::One,
+            StopBits::Two => serialport::StopBits::Two,
+        }
+    }
+}
+
+/// Flow control mode, see [`SerialBuilder::flow_control`]
+///
+/// Mirrors [`serialport::FlowControl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlowControl {
+    /// No flow control
+    None,
+    /// XON/XOFF characters in the data stream
+    Software,
+    /// RTS/CTS signals
+    Hardware,
+}
+
+impl From<serialport::FlowControl> for FlowControl {
+    fn from(flow_control: serialport::FlowControl) -> Self {
+        match flow_control {
+            serialport::FlowControl::None => FlowControl::None,
+            serialport::FlowControl::Software => FlowControl::Software,
+            serialport::FlowControl::Hardware => FlowControl::Hardware,
+        }
+    }
+}
+
+impl From<FlowControl> for serialport::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        }
+    }
+}
+
+/// Line settings of a [`Serial`] port, as returned by [`Serial::settings`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    /// Baud rate in symbols per second
+    pub baud_rate: u32,
+    /// Number of bits per character
+    pub data_bits: DataBits,
+    /// Parity checking mode
+    pub parity: Parity,
+    /// Number of stop bits
+    pub stop_bits: StopBits,
+    /// Flow control mode
+    pub flow_control: FlowControl,
+}
+
+/// Helper to convert a serialport::Error to a SerialError
+fn serialport_error(err: serialport::Error) -> SerialError {
+    let err = match err.kind() {
+        serialport::ErrorKind::NoDevice => IoErrorKind::NotFound,
+        serialport::ErrorKind::InvalidInput => IoErrorKind::InvalidInput,
+        serialport::ErrorKind::Io(kind) => kind,
+        serialport::ErrorKind::Unknown => IoErrorKind::Other,
+    };
+    SerialError { err }
 }
 
 /// Helper to convert std::io::Error to the nb::Error
@@ -80,6 +483,12 @@ impl fmt::Display for SerialError {
 
 impl std::error::Error for SerialError {}
 
+impl From<SerialError> for std::io::Error {
+    fn from(err: SerialError) -> Self {
+        std::io::Error::from(err.err)
+    }
+}
+
 impl embedded_hal_nb::serial::Error for SerialError {
     #[allow(clippy::match_single_binding)]
     fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
@@ -111,6 +520,129 @@ mod test {
         let _serial = Serial::open_from_builder(builder).expect("Creating TTYPort failed");
     }
 
+    #[test]
+    fn create_serial_from_builder_with_serialport_settings() {
+        let (_master, _slave, name) =
+            openpty::openpty(None, None, None).expect("Creating pty failed");
+        let builder = serialport::new(name, 9600).parity(serialport::Parity::Odd);
+        let _serial = Serial::open_from_builder(builder).expect("Creating TTYPort failed");
+    }
+
+    #[test]
+    fn create_serial_with_builder() {
+        let (_master, _slave, name) =
+            openpty::openpty(None, None, None).expect("Creating pty failed");
+        let serial = Serial::builder(name)
+            .baud_rate(115_200)
+            .data_bits(DataBits::Seven)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::Two)
+            .flow_control(FlowControl::None)
+            .timeout(Duration::from_millis(10))
+            .open()
+            .expect("Creating TTYPort failed");
+        assert_eq!(
+            serialport::SerialPort::timeout(&serial.0),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        let (_master, _slave, name) =
+            openpty::openpty(None, None, None).expect("Creating pty failed");
+        let serial = Serial::builder(name)
+            .baud_rate(115_200)
+            .open()
+            .expect("Creating TTYPort failed");
+        let settings = serial.settings().unwrap();
+        assert_eq!(settings.baud_rate, 115_200);
+        assert_eq!(settings.flow_control, FlowControl::None);
+    }
+
+    #[test]
+    fn test_serialport_error() {
+        let err = serialport::Error::new(serialport::ErrorKind::NoDevice, "gone");
+        assert_eq!(*serialport_error(err).inner(), IoErrorKind::NotFound);
+        let err = serialport::Error::new(serialport::ErrorKind::Io(IoErrorKind::TimedOut), "");
+        assert_eq!(*serialport_error(err).inner(), IoErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err = SerialError {
+            err: IoErrorKind::TimedOut,
+        };
+        assert_eq!(std::io::Error::from(err).kind(), IoErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_settings_conversions() {
+        assert_eq!(
+            serialport::DataBits::from(DataBits::Five),
+            serialport::DataBits::Five
+        );
+        assert_eq!(
+            serialport::DataBits::from(DataBits::Eight),
+            serialport::DataBits::Eight
+        );
+        assert_eq!(
+            serialport::Parity::from(Parity::Odd),
+            serialport::Parity::Odd
+        );
+        assert_eq!(
+            serialport::StopBits::from(StopBits::Two),
+            serialport::StopBits::Two
+        );
+        assert_eq!(
+            serialport::FlowControl::from(FlowControl::Hardware),
+            serialport::FlowControl::Hardware
+        );
+        assert_eq!(DataBits::from(serialport::DataBits::Six), DataBits::Six);
+        assert_eq!(Parity::from(serialport::Parity::Even), Parity::Even);
+        assert_eq!(StopBits::from(serialport::StopBits::One), StopBits::One);
+        assert_eq!(
+            FlowControl::from(serialport::FlowControl::Software),
+            FlowControl::Software
+        );
+    }
+
+    #[test]
+    fn create_serial_from_fd() {
+        let (mut master, slave, _name) =
+            openpty::openpty(None, None, None).expect("Creating pty failed");
+        let mut serial = Serial::from_fd(OwnedFd::from(slave));
+        assert_eq!(serial.timeout(), Duration::ZERO);
+        assert_eq!(Err(nb::Error::WouldBlock), serial.read());
+        // The pty is left in canonical mode, so input is only available per line
+        master.write_all(&[1]).expect("Write failed");
+        assert_eq!(Err(nb::Error::WouldBlock), serial.read());
+        master.write_all(b"\n").expect("Write failed");
+        assert_eq!(Ok(1), serial.read());
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let (_master, _slave, name) =
+            openpty::openpty(None, None, None).expect("Creating pty failed");
+        let mut serial = Serial::open(name.clone(), 9600).expect("Creating TTYPort failed");
+        assert!(serial.exclusive());
+        assert!(Serial::open(name.clone(), 9600).is_err());
+        serial.set_exclusive(false).unwrap();
+        assert!(!serial.exclusive());
+        let _other = std::fs::File::open(&name).expect("Opening shared port failed");
+    }
+
+    #[test]
+    fn test_close() {
+        let (mut master, mut serial) = create_pty_and_serial();
+        serial.write(1).unwrap();
+        serial.close().unwrap();
+        let mut buf = [0];
+        master.read_exact(&mut buf).expect("Read failed");
+        assert_eq!(buf, [1]);
+    }
+
     #[test]
     fn test_empty_read() {
         let (mut _master, mut serial) = create_pty_and_serial();
@@ -124,6 +656,58 @@ mod test {
         assert_eq!(Ok(1), serial.read());
     }
 
+    #[test]
+    fn test_read_available() {
+        let (mut master, mut serial) = create_pty_and_serial();
+        let mut buf = [0; 8];
+        assert_eq!(Err(nb::Error::WouldBlock), serial.read_available(&mut buf));
+        master.write_all(&[1, 2, 3]).expect("Write failed");
+        assert_eq!(Ok(3), serial.read_available(&mut buf));
+        assert_eq!(buf[..3], [1, 2, 3]);
+        assert_eq!(Err(nb::Error::WouldBlock), serial.read_available(&mut buf));
+    }
+
+    #[test]
+    fn test_read_with_timeout() {
+        let (mut master, mut serial) = create_pty_and_serial();
+        serial.set_timeout(Duration::from_millis(20)).unwrap();
+        assert_eq!(serial.timeout(), Duration::from_millis(20));
+        let mut buf = [0; 8];
+
+        let before = std::time::Instant::now();
+        let err = serial.read_with_timeout(&mut buf).unwrap_err();
+        assert_eq!(*err.inner(), IoErrorKind::TimedOut);
+        assert!(before.elapsed() >= Duration::from_millis(20));
+
+        master.write_all(&[4, 5]).expect("Write failed");
+        assert_eq!(Ok(2), serial.read_with_timeout(&mut buf));
+        assert_eq!(buf[..2], [4, 5]);
+
+        // The nb path still reports an elapsed timeout as `WouldBlock`
+        assert_eq!(Err(nb::Error::WouldBlock), serial.read());
+    }
+
+    #[test]
+    fn test_bytes_with_timeout() {
+        let (mut master, mut serial) = create_pty_and_serial();
+        master.write_all(b"OK\r\n").expect("Write failed");
+        let bytes: Result<Vec<u8>, _> = serial
+            .bytes_with_timeout(Duration::from_millis(20))
+            .collect();
+        assert_eq!(bytes.unwrap(), b"OK\r\n");
+        assert_eq!(serial.timeout(), Duration::ZERO);
+
+        // Bytes after the end of a line are left for the next read
+        master.write_all(b"A\nB").expect("Write failed");
+        let line: Vec<u8> = serial
+            .bytes_with_timeout(Duration::from_millis(20))
+            .map(Result::unwrap)
+            .take_while(|&b| b != b'\n')
+            .collect();
+        assert_eq!(line, b"A");
+        assert_eq!(Ok(b'B'), serial.read());
+    }
+
     #[test]
     fn test_write() {
         let (mut master, mut serial) = create_pty_and_serial();
@@ -0,0 +1,220 @@
+//! Sharing of an [`embedded-hal`] I2C bus between several device drivers
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use std::cell::RefCell;
+use std::sync::Mutex;
+
+use embedded_hal::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
+
+/// I2C bus shared by the device handles it hands out, within a single thread
+///
+/// Each handle created by [`RefCellI2c::device`] is bound to one address and implements
+/// [`I2c`] by performing its transactions on the shared bus, so every driver can own its own
+/// handle while using a single open [`I2cdev`](crate::I2cdev). Transactions of a handle always
+/// go to the address it is bound to; the address passed by its driver is ignored. Since each
+/// transaction carries its address, handles for different addresses can be used in any order.
+///
+/// Use [`MutexI2c`] to share the bus between threads.
+///
+/// ```no_run
+/// # #[cfg(feature = "i2c")] {
+/// use embedded_hal::i2c::I2c;
+/// use linux_embedded_hal::{I2cdev, RefCellI2c};
+///
+/// let bus = RefCellI2c::new(I2cdev::new("/dev/i2c-1").unwrap());
+/// let mut sensor = bus.device(0x48);
+/// let mut eeprom = bus.device(0x50);
+/// sensor.write(0x48, &[0x00]).unwrap();
+/// eeprom.write(0x50, &[0x00, 0x10]).unwrap();
+/// # }
+/// ```
+pub struct RefCellI2c<T>(RefCell<T>);
+
+impl<T> RefCellI2c<T> {
+    /// Share `bus`
+    pub fn new(bus: T) -> Self {
+        RefCellI2c(RefCell::new(bus))
+    }
+
+    /// Create a handle for the device at `address`
+    pub fn device(&self, address: SevenBitAddress) -> RefCellI2cDevice<'_, T> {
+        RefCellI2cDevice { bus: self, address }
+    }
+
+    /// Release the bus
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+/// Handle for a device on a [`RefCellI2c`] bus, created by [`RefCellI2c::device`]
+pub struct RefCellI2cDevice<'a, T> {
+    bus: &'a RefCellI2c<T>,
+    address: SevenBitAddress,
+}
+
+impl<T> RefCellI2cDevice<'_, T> {
+    /// The address this handle is bound to
+    pub fn address(&self) -> SevenBitAddress {
+        self.address
+    }
+}
+
+impl<T: ErrorType> ErrorType for RefCellI2cDevice<'_, T> {
+    type Error = T::Error;
+}
+
+impl<T: I2c> I2c for RefCellI2cDevice<'_, T> {
+    fn transaction(
+        &mut self,
+        _address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.bus
+            .0
+            .borrow_mut()
+            .transaction(self.address, operations)
+    }
+}
+
+/// I2C bus shared by the device handles it hands out, across threads
+///
+/// This is the thread-safe counterpart of [`RefCellI2c`]: each transaction holds a lock on
+/// the bus for its whole duration. Handles are bound to one address, and transactions of a
+/// handle always go to that address; the address passed by its driver is ignored.
+pub struct MutexI2c<T>(Mutex<T>);
+
+impl<T> MutexI2c<T> {
+    /// Share `bus`
+    pub fn new(bus: T) -> Self {
+        MutexI2c(Mutex::new(bus))
+    }
+
+    /// Create a handle for the device at `address`
+    pub fn device(&self, address: SevenBitAddress) -> MutexI2cDevice<'_, T> {
+        MutexI2cDevice { bus: self, address }
+    }
+
+    /// Release the bus
+    pub fn into_inner(self) -> T {
+        self.0.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Handle for a device on a [`MutexI2c`] bus, created by [`MutexI2c::device`]
+pub struct MutexI2cDevice<'a, T> {
+    bus: &'a MutexI2c<T>,
+    address: SevenBitAddress,
+}
+
+impl<T> MutexI2cDevice<'_, T> {
+    /// The address this handle is bound to
+    pub fn address(&self) -> SevenBitAddress {
+        self.address
+    }
+}
+
+impl<T: ErrorType> ErrorType for MutexI2cDevice<'_, T> {
+    type Error = T::Error;
+}
+
+impl<T: I2c> I2c for MutexI2cDevice<'_, T> {
+    fn transaction(
+        &mut self,
+        _address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        // A panic in another handle's transaction leaves no state behind that could make
+        // this one unsafe to perform, so a poisoned lock is used anyway.
+        let mut bus = self.bus.0.lock().unwrap_or_else(|e| e.into_inner());
+        bus.transaction(self.address, operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// Bus recording the address and written bytes of each transaction, and answering reads
+    /// with the address
+    #[derive(Default)]
+    struct MockBus(Vec<(u8, Vec<u8>)>);
+
+    impl ErrorType for MockBus {
+        type Error = Infallible;
+    }
+
+    impl I2c for MockBus {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            let mut written = Vec::new();
+            for op in operations {
+                match op {
+                    Operation::Write(buf) => written.extend_from_slice(buf),
+                    Operation::Read(buf) => buf.fill(address),
+                }
+            }
+            self.0.push((address, written));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_refcell_devices() {
+        let bus = RefCellI2c::new(MockBus::default());
+        let mut a = bus.device(0x48);
+        let mut b = bus.device(0x50);
+        assert_eq!((a.address(), b.address()), (0x48, 0x50));
+
+        let mut read = [0; 2];
+        a.write(0x48, &[1]).unwrap();
+        b.write_read(0x50, &[2], &mut read).unwrap();
+        assert_eq!(read, [0x50; 2]);
+        a.read(0x48, &mut read).unwrap();
+        assert_eq!(read, [0x48; 2]);
+        // The bound address wins over the one passed by the driver
+        b.write(0x48, &[3]).unwrap();
+
+        assert_eq!(
+            bus.into_inner().0,
+            [
+                (0x48, vec![1]),
+                (0x50, vec![2]),
+                (0x48, vec![]),
+                (0x50, vec![3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mutex_devices() {
+        let bus = MutexI2c::new(MockBus::default());
+        std::thread::scope(|s| {
+            for address in [0x48, 0x50] {
+                let mut dev = bus.device(address);
+                s.spawn(move || {
+                    for i in 0..10 {
+                        let mut read = [0];
+                        dev.write_read(address, &[i], &mut read).unwrap();
+                        assert_eq!(read, [address]);
+                    }
+                });
+            }
+        });
+
+        let log = bus.into_inner().0;
+        for address in [0x48, 0x50] {
+            let written: Vec<_> = log
+                .iter()
+                .filter(|(a, _)| *a == address)
+                .map(|(_, w)| w[0])
+                .collect();
+            assert_eq!(written, (0..10).collect::<Vec<_>>());
+        }
+    }
+}
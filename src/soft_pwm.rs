@@ -0,0 +1,440 @@
+//! Software PWM toggling an [`embedded-hal`] digital output pin from a thread
+//!
+//! [`embedded-hal`]: https://docs.rs/embedded-hal
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::pwm::{ErrorKind, ErrorType, SetDutyCycle};
+
+/// Duty cycle of a [`SoftPwm`] driving the pin high for the whole period
+const MAX_DUTY: u16 = 1000;
+
+/// PWM signal generated in software on an output pin, for low-frequency uses such as servos
+/// or LED dimming on pins without hardware PWM
+///
+/// A thread drives the pin high at the start of each period and low once the high time is
+/// over, sleeping in between. Implements [`SetDutyCycle`] with a resolution of 1000 steps,
+/// i.e. 0.1%.
+///
+/// This is best-effort: Linux is not a realtime kernel, so every edge comes late by the
+/// wakeup latency of the thread, typically 50 to 100 microseconds, and by milliseconds or
+/// more on a loaded system, when the thread is preempted. A 1% duty cycle at 100 Hz is a
+/// pulse of 100 microseconds, as long as that jitter, so only periods from a few to some tens
+/// of milliseconds, i.e. tens to a few hundreds of Hz, give usable signals, and only duty
+/// cycles well above the jitter are accurate. Periods are scheduled from the start of the
+/// previous one, so the frequency doesn't drift, but a late period is shorter. A servo
+/// tolerates this, though it may twitch; audio, motor drivers or anything needing a
+/// precise or high frequency need a hardware PWM, e.g. through `/sys/class/pwm`.
+///
+/// Changes of the duty cycle or period take effect at the start of the next period. When the
+/// `SoftPwm` is dropped or [released](SoftPwm::release), the thread stops and drives the pin
+/// low. If driving the pin fails, the thread stops, and the error is returned by the next
+/// call to [`SoftPwm::set_duty_cycle`].
+///
+/// ```no_run
+/// # #[cfg(feature = "gpio_cdev")] {
+/// use std::time::Duration;
+/// use embedded_hal::pwm::SetDutyCycle;
+/// use gpio_cdev::{Chip, LineRequestFlags};
+/// use linux_embedded_hal::{CdevPin, SoftPwm};
+///
+/// let mut chip = Chip::new("/dev/gpiochip0").unwrap();
+/// let line = chip.get_line(18).unwrap();
+/// let pin = CdevPin::new(line.request(LineRequestFlags::OUTPUT, 0, "servo").unwrap()).unwrap();
+///
+/// // Servo pulses of 1.5 ms every 20 ms
+/// let mut pwm = SoftPwm::new(pin, Duration::from_millis(20)).unwrap();
+/// pwm.set_duty_cycle_fraction(15, 200).unwrap();
+/// # }
+/// ```
+///
+/// [`SoftPwm::set_duty_cycle`]: SetDutyCycle::set_duty_cycle
+pub struct SoftPwm<P: OutputPin> {
+    shared: Arc<Shared<P::Error>>,
+    thread: Option<JoinHandle<P>>,
+}
+
+/// State shared with the thread
+struct Shared<E> {
+    state: Mutex<State<E>>,
+    changed: Condvar,
+}
+
+struct State<E> {
+    period: Duration,
+    duty: u16,
+    stop: bool,
+    /// Error that stopped the thread, until reported
+    error: Option<E>,
+    /// Whether the thread stopped after an error
+    failed: bool,
+}
+
+impl<P> SoftPwm<P>
+where
+    P: OutputPin + Send + 'static,
+    P::Error: Send,
+{
+    /// Start generating a signal with the given `period` on `pin`, with a duty cycle of 0, so
+    /// the pin is low until the duty cycle is set.
+    ///
+    /// Fails with [`SoftPwmError::ZeroPeriod`] if `period` is zero.
+    pub fn new(pin: P, period: Duration) -> Result<Self, SoftPwmError<P::Error>> {
+        check_period(period)?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                period,
+                duty: 0,
+                stop: false,
+                error: None,
+                failed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = thread::spawn({
+            let shared = shared.clone();
+            move || run(pin, &shared)
+        });
+        Ok(SoftPwm {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Set the period of the signal, keeping the duty cycle as a fraction of it.
+    ///
+    /// Fails with [`SoftPwmError::ZeroPeriod`] if `period` is zero, keeping the previous one.
+    pub fn set_period(&mut self, period: Duration) -> Result<(), SoftPwmError<P::Error>> {
+        check_period(period)?;
+        self.shared.lock().period = period;
+        Ok(())
+    }
+
+    /// The period of the signal
+    pub fn period(&self) -> Duration {
+        self.shared.lock().period
+    }
+
+    /// Stop the signal, drive the pin low and return it.
+    pub fn release(mut self) -> P {
+        self.stop().expect("the PWM thread is only joined once")
+    }
+
+    /// Stop the thread and return the pin, unless already done.
+    fn stop(&mut self) -> Option<P> {
+        let thread = self.thread.take()?;
+        self.shared.lock().stop = true;
+        self.shared.changed.notify_all();
+        match thread.join() {
+            Ok(pin) => Some(pin),
+            // The pin panicked, which the thread doesn't catch
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<P: OutputPin> Drop for SoftPwm<P> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shared.lock().stop = true;
+            self.shared.changed.notify_all();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<E> Shared<E> {
+    fn lock(&self) -> MutexGuard<'_, State<E>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sleep until `deadline`, and return `false` if asked to stop in the meantime.
+    fn sleep_until(&self, deadline: Instant) -> bool {
+        let mut state = self.lock();
+        loop {
+            if state.stop {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+/// Generate the signal on `pin` until asked to stop or driving it fails, then return it.
+fn run<P: OutputPin>(mut pin: P, shared: &Shared<P::Error>) -> P {
+    let mut start = Instant::now();
+    loop {
+        let (period, duty) = {
+            let state = shared.lock();
+            (state.period, state.duty)
+        };
+        match run_period(&mut pin, shared, start, period, high_time(period, duty)) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                let mut state = shared.lock();
+                state.error = Some(err);
+                state.failed = true;
+                return pin;
+            }
+        }
+        start += period;
+        // After falling behind by more than a period, resume from now rather than
+        // catching up with a burst of short periods
+        let now = Instant::now();
+        if now > start + period {
+            start = now;
+        }
+    }
+    let _ = pin.set_low();
+    pin
+}
+
+/// Generate the period starting at `start`, and return `false` if asked to stop during it.
+fn run_period<P: OutputPin>(
+    pin: &mut P,
+    shared: &Shared<P::Error>,
+    start: Instant,
+    period: Duration,
+    high: Duration,
+) -> Result<bool, P::Error> {
+    if high > Duration::ZERO {
+        pin.set_high()?;
+        if !shared.sleep_until(start + high) {
+            return Ok(false);
+        }
+    }
+    if high < period {
+        pin.set_low()?;
+    }
+    Ok(shared.sleep_until(start + period))
+}
+
+/// Fail if `period` is zero, which would keep the thread from ever sleeping.
+fn check_period<E>(period: Duration) -> Result<(), SoftPwmError<E>> {
+    if period.is_zero() {
+        return Err(SoftPwmError::ZeroPeriod);
+    }
+    Ok(())
+}
+
+/// Time the pin is high during each `period`, at a duty cycle of `duty` out of `MAX_DUTY`
+fn high_time(period: Duration, duty: u16) -> Duration {
+    period * u32::from(duty.min(MAX_DUTY)) / u32::from(MAX_DUTY)
+}
+
+impl<P> ErrorType for SoftPwm<P>
+where
+    P: OutputPin,
+{
+    type Error = SoftPwmError<P::Error>;
+}
+
+impl<P> SetDutyCycle for SoftPwm<P>
+where
+    P: OutputPin,
+{
+    fn max_duty_cycle(&self) -> u16 {
+        MAX_DUTY
+    }
+
+    /// Set the duty cycle, out of 1000, for the next periods. Larger values are treated as
+    /// 1000, keeping the pin high.
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        let mut state = self.shared.lock();
+        if let Some(err) = state.error.take() {
+            return Err(SoftPwmError::Pin(err));
+        }
+        if state.failed {
+            return Err(SoftPwmError::Stopped);
+        }
+        state.duty = duty.min(MAX_DUTY);
+        Ok(())
+    }
+}
+
+/// Error of a [`SoftPwm`]
+#[derive(Debug)]
+pub enum SoftPwmError<E> {
+    /// Driving the pin failed, which stopped the signal
+    Pin(E),
+    /// The signal stopped after an error reported earlier
+    Stopped,
+    /// The period requested is zero
+    ZeroPeriod,
+}
+
+impl<E: fmt::Display> fmt::Display for SoftPwmError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoftPwmError::Pin(err) => write!(f, "{}", err),
+            SoftPwmError::Stopped => write!(f, "PWM stopped after a pin error"),
+            SoftPwmError::ZeroPeriod => write!(f, "PWM period is zero"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for SoftPwmError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SoftPwmError::Pin(err) => Some(err),
+            SoftPwmError::Stopped | SoftPwmError::ZeroPeriod => None,
+        }
+    }
+}
+
+impl<E: fmt::Debug> embedded_hal::pwm::Error for SoftPwmError<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal::digital::ErrorType as PinErrorType;
+
+    /// Pin recording its edges, failing once `fail_after` edges were made
+    struct Pin {
+        edges: Arc<Mutex<Vec<(Instant, bool)>>>,
+        fail_after: usize,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Broken;
+
+    impl embedded_hal::digital::Error for Broken {
+        fn kind(&self) -> embedded_hal::digital::ErrorKind {
+            embedded_hal::digital::ErrorKind::Other
+        }
+    }
+
+    impl PinErrorType for Pin {
+        type Error = Broken;
+    }
+
+    impl Pin {
+        fn set(&mut self, high: bool) -> Result<(), Broken> {
+            let mut edges = self.edges.lock().unwrap();
+            if edges.len() >= self.fail_after {
+                return Err(Broken);
+            }
+            edges.push((Instant::now(), high));
+            Ok(())
+        }
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Broken> {
+            self.set(false)
+        }
+
+        fn set_high(&mut self) -> Result<(), Broken> {
+            self.set(true)
+        }
+    }
+
+    #[test]
+    fn test_high_time() {
+        let period = Duration::from_millis(20);
+        assert_eq!(high_time(period, 0), Duration::ZERO);
+        assert_eq!(high_time(period, 75), Duration::from_micros(1500));
+        assert_eq!(high_time(period, MAX_DUTY), period);
+        assert_eq!(high_time(period, u16::MAX), period);
+    }
+
+    #[test]
+    fn test_soft_pwm() {
+        let edges = Arc::new(Mutex::new(Vec::new()));
+        let pin = Pin {
+            edges: edges.clone(),
+            fail_after: usize::MAX,
+        };
+        let period = Duration::from_millis(20);
+        let mut pwm = SoftPwm::new(pin, period).unwrap();
+        pwm.set_duty_cycle_percent(25).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        let pin = pwm.release();
+        assert_eq!(pin.edges.lock().unwrap().last().map(|e| e.1), Some(false));
+
+        let edges = edges.lock().unwrap();
+        let rises: Vec<_> = edges
+            .windows(2)
+            .filter(|pair| pair[1].1 && !pair[0].1)
+            .map(|pair| pair[1].0)
+            .collect();
+        assert!(rises.len() >= 4, "{} periods", rises.len());
+        // Periods are kept on schedule, and pulses last a quarter of them. Edges come late by
+        // the wakeup latency of the thread, which is unbounded on a loaded system, so only
+        // gross errors are checked.
+        let span = *rises.last().unwrap() - rises[0];
+        let periods = rises.len() as u32 - 1;
+        assert!(span >= period * periods - period / 2);
+        assert!(span < period * periods * 5);
+        // The last edge is the one stopping the signal, possibly in the middle of a pulse
+        let complete = &edges[..edges.len() - 1];
+        // A late wakeup stretches or shortens a single pulse, so the median one is checked
+        let mut pulses: Vec<_> = complete
+            .windows(2)
+            .filter(|pair| pair[0].1 && !pair[1].1)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .collect();
+        pulses.sort();
+        let median = pulses[pulses.len() / 2];
+        assert!(
+            median > Duration::from_millis(1) && median < period,
+            "{:?}",
+            median
+        );
+    }
+
+    #[test]
+    fn test_soft_pwm_pin_error() {
+        let pin = Pin {
+            edges: Arc::new(Mutex::new(Vec::new())),
+            fail_after: 3,
+        };
+        let mut pwm = SoftPwm::new(pin, Duration::from_millis(5)).unwrap();
+        pwm.set_duty_cycle_fully_on().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(matches!(
+            pwm.set_duty_cycle(0),
+            Err(SoftPwmError::Pin(Broken))
+        ));
+        assert!(matches!(pwm.set_duty_cycle(0), Err(SoftPwmError::Stopped)));
+        assert_eq!(pwm.release().edges.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_soft_pwm_zero_period() {
+        let edges = Arc::new(Mutex::new(Vec::new()));
+        let pin = || Pin {
+            edges: edges.clone(),
+            fail_after: usize::MAX,
+        };
+        assert!(matches!(
+            SoftPwm::new(pin(), Duration::ZERO),
+            Err(SoftPwmError::ZeroPeriod)
+        ));
+
+        let period = Duration::from_millis(20);
+        let mut pwm = SoftPwm::new(pin(), period).unwrap();
+        assert!(matches!(
+            pwm.set_period(Duration::ZERO),
+            Err(SoftPwmError::ZeroPeriod)
+        ));
+        assert_eq!(pwm.period(), period);
+    }
+}
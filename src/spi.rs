@@ -7,8 +7,11 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::io;
 use std::ops;
+use std::os::unix::io::RawFd;
 use std::path::Path;
 
+use crate::retry::retry_interrupted;
+
 /// Spidev wrapper providing the embedded-hal [`SpiDevice`] trait.
 ///
 /// Use this struct when you want a single spidev device, using a Linux-managed CS (chip-select) pin,
@@ -20,16 +23,38 @@ use std::path::Path;
 /// instead; for that refer to [`SpidevBus`] below. You may also want to use [`SpiBus`]
 /// if you want to handle all the CS pins yourself using GPIO.
 ///
-/// This struct wraps a [`spidev::Spidev`] struct, so it can be constructed directly
-/// and the inner struct accessed if needed, for example to (re)configure the SPI settings.
+/// This struct is created from a [`spidev::Spidev`] with [`SpidevDevice::new`] or
+/// `From<Spidev>`. The inner struct can be accessed if needed, for example to (re)configure
+/// the SPI settings.
+///
+/// Reconfigure the device through [`DerefMut`](ops::DerefMut), e.g. `dev.configure(&options)`,
+/// rather than through the public field: the mode is cached for the 3-wire check of
+/// [transactions](embedded_hal::spi::SpiDevice::transaction), and only a mutable borrow of
+/// the `SpidevDevice` itself clears it. After changing the mode with
+/// `dev.0.configure(&options)`, the check keeps using the old one until `&mut *dev` is taken.
 ///
-/// Note that [delay operations] on this device are capped to 65535 microseconds.
+/// [Delay operations] are performed by the kernel while CS stays asserted. The kernel takes
+/// delays in whole microseconds of at most 65535 each, so longer delays are split into
+/// several consecutive delay transfers.
 ///
 /// [`SpiDevice`]: embedded_hal::spi::SpiDevice
 /// [`SpiBus`]: embedded_hal::spi::SpiBus
 /// [`spidev::Spidev`]: spidev::Spidev
-/// [delay operations]: embedded_hal::spi::Operation::DelayUs
-pub struct SpidevDevice(pub spidev::Spidev);
+/// [Delay operations]: embedded_hal::spi::Operation::DelayNs
+pub struct SpidevDevice(pub spidev::Spidev, DeviceSettings);
+
+/// Crate-level settings of a [`SpidevDevice`]
+#[derive(Debug, Clone, Default)]
+struct DeviceSettings {
+    /// Delay inserted by the controller between words, in microseconds
+    word_delay_us: u8,
+    /// Byte clocked out during reads, if not left to the controller
+    dummy_byte: Option<u8>,
+    /// Mode flags last read or written through this handle, `None` until then and once the
+    /// inner `Spidev` has been borrowed mutably, which may have changed them
+    mode: Option<u32>,
+    stats: Stats,
+}
 
 /// Spidev wrapper providing the embedded-hal [`SpiBus`] trait.
 ///
@@ -42,8 +67,8 @@ pub struct SpidevDevice(pub spidev::Spidev);
 /// device tree; you will not be able to drive CS pins that are already used by `spidev`
 /// as GPIOs. Instead use [`SpidevDevice`].
 ///
-/// This struct must still be created from a [`spidev::Spidev`] device, but there are two
-/// important notes:
+/// This struct must still be created from a [`spidev::Spidev`] device, with
+/// [`SpidevBus::new`] or `From<Spidev>`, but there are two important notes:
 ///
 /// 1. The CS pin associated with this `spidev` device will be driven whenever any device accesses
 ///    this bus, so it should be an unconnected or unused pin.
@@ -61,12 +86,245 @@ pub struct SpidevDevice(pub spidev::Spidev);
 /// [`SpiBus`]: embedded_hal::spi::SpiBus
 /// [`embedded-hal-bus`]: https://docs.rs/embedded-hal-bus/
 /// [`spidev::Spidev`]: spidev::Spidev
-/// [delay operations]: embedded_hal::spi::Operation::DelayUs
 /// [configure]: spidev::Spidev::configure
 /// [`SPI_NO_CS`]: spidev::SpiModeFlags::SPI_NO_CS
-pub struct SpidevBus(pub spidev::Spidev);
+pub struct SpidevBus(pub spidev::Spidev, BusSettings);
+
+/// Crate-level settings of a [`SpidevBus`]
+#[derive(Debug, Clone)]
+struct BusSettings {
+    /// Byte clocked out while reading past the end of the write buffer
+    dummy_byte: u8,
+    /// Largest number of bytes sent in a single kernel call
+    max_transfer_size: usize,
+    /// Number of bytes each word takes in buffers
+    word_bytes: usize,
+    /// Staging buffer for padded write data, kept across transfers
+    scratch: Vec<u8>,
+    stats: Stats,
+}
+
+impl Default for BusSettings {
+    fn default() -> Self {
+        BusSettings {
+            dummy_byte: 0,
+            max_transfer_size: spidev_bufsiz().unwrap_or(DEFAULT_BUFSIZ),
+            word_bytes: 1,
+            scratch: Vec::new(),
+            stats: Stats::default(),
+        }
+    }
+}
+
+impl BusSettings {
+    /// Largest number of bytes sent in a single kernel call, keeping words whole
+    fn chunk_size(&self) -> usize {
+        let size = self.max_transfer_size - self.max_transfer_size % self.word_bytes;
+        size.max(self.word_bytes)
+    }
+
+    /// Fail unless `len` bytes make up whole words.
+    fn check_words(&self, len: usize) -> Result<(), SPIError> {
+        // `usize::is_multiple_of` needs a newer compiler than the MSRV
+        let partial = len % self.word_bytes;
+        if partial != 0 {
+            return Err(SPIError::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "buffer of {} bytes holds no whole number of {}-byte words",
+                    len, self.word_bytes
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Open the spidev device at `path` with the given access mode.
+fn open_file(path: &Path, read: bool, write: bool) -> Result<spidev::Spidev, SPIError> {
+    let file = std::fs::OpenOptions::new()
+        .read(read)
+        .write(write)
+        .open(path)?;
+    Ok(spidev::Spidev::new(file))
+}
+
+/// Fail unless `bits` is a word size spidev accepts.
+fn check_bits_per_word(bits: u8) -> Result<(), SPIError> {
+    if !(1..=32).contains(&bits) {
+        return Err(SPIError::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported SPI word size of {} bits", bits),
+        )));
+    }
+    Ok(())
+}
+
+/// Number of bytes spidev uses for each word of `bits` bits in buffers
+fn word_bytes(bits: u8) -> usize {
+    match bits {
+        0..=8 => 1,
+        9..=16 => 2,
+        _ => 4,
+    }
+}
+
+/// Default size of the spidev message buffer, used when it can't be read from sysfs
+const DEFAULT_BUFSIZ: usize = 4096;
+
+/// Read the size of the spidev message buffer, the `bufsiz` module parameter.
+fn spidev_bufsiz() -> Option<usize> {
+    let bufsiz = std::fs::read_to_string("/sys/module/spidev/parameters/bufsiz").ok()?;
+    bufsiz.trim().parse().ok().filter(|&n| n > 0)
+}
+
+// Read and write all 32 bits of the mode, `SPI_IOC_RD_MODE32` and `SPI_IOC_WR_MODE32`
+nix::ioctl_read!(spi_rd_mode32, b'k', 5, u32);
+nix::ioctl_write_ptr!(spi_wr_mode32, b'k', 5, u32);
+
+/// Read the mode flags of the spidev device open as `fd`.
+///
+/// Unlike [`spidev::spidevioctl::get_mode`], which reads the first 8 flags only, this also
+/// reads the dual, quad and octal transfer flags above them.
+fn read_mode(fd: RawFd) -> io::Result<u32> {
+    let mut mode = 0;
+    // SAFETY: SPI_IOC_RD_MODE32 only writes a u32 to the pointer passed
+    unsafe { spi_rd_mode32(fd, &mut mode) }?;
+    Ok(mode)
+}
+
+/// Set all the mode flags of the spidev device open as `fd`.
+fn write_mode(fd: RawFd, mode: u32) -> io::Result<()> {
+    // SAFETY: SPI_IOC_WR_MODE32 only reads a u32 from the pointer passed
+    unsafe { spi_wr_mode32(fd, &mode) }?;
+    Ok(())
+}
+
+/// Transfer counters of a [`SpidevBus`] or [`SpidevDevice`]
+#[derive(Debug, Clone, Default)]
+struct Stats {
+    written: u64,
+    read: u64,
+    transfers: u64,
+}
+
+impl Stats {
+    /// Account for a successful transfer writing `written` and reading `read` bytes
+    fn record(&mut self, written: usize, read: usize) {
+        self.written += written as u64;
+        self.read += read as u64;
+        self.transfers += 1;
+    }
+}
+
+/// Snapshot of the configuration of a spidev device, as reported by the kernel
+///
+/// Returned by [`SpidevDevice::config`] and [`SpidevBus::config`]. It's a plain value:
+/// reconfiguring the device afterwards doesn't update it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpiConfig {
+    /// Clock polarity: the clock idles high
+    pub cpol: bool,
+    /// Clock phase: data is sampled on the trailing clock edge
+    pub cpha: bool,
+    /// Chip select is active high
+    pub cs_active_high: bool,
+    /// Words are sent least significant bit first
+    pub lsb_first: bool,
+    /// Data in and out share a single line (half duplex)
+    pub three_wire: bool,
+    /// Chip select isn't driven at all
+    pub no_cs: bool,
+    /// Data out is looped back to data in by the controller
+    pub loop_back: bool,
+    /// Default maximum clock speed, in Hz
+    ///
+    /// This is the speed last requested, as spidev reports it back unchanged. The clock
+    /// actually generated may be lower, as controllers round it down to a divisor of their
+    /// input clock for each transfer, without reporting it to user space.
+    pub max_speed_hz: u32,
+    /// Word size, in bits
+    pub bits_per_word: u8,
+}
+
+impl SpiConfig {
+    /// Build a snapshot from the mode byte and settings returned by the kernel.
+    fn from_mode(mode: u8, max_speed_hz: u32, bits_per_word: u8) -> Self {
+        use spidev::SpiModeFlags;
+
+        let mode = SpiModeFlags::from_bits_truncate(u32::from(mode));
+        SpiConfig {
+            cpol: mode.contains(SpiModeFlags::SPI_CPOL),
+            cpha: mode.contains(SpiModeFlags::SPI_CPHA),
+            cs_active_high: mode.contains(SpiModeFlags::SPI_CS_HIGH),
+            lsb_first: mode.contains(SpiModeFlags::SPI_LSB_FIRST),
+            three_wire: mode.contains(SpiModeFlags::SPI_3WIRE),
+            no_cs: mode.contains(SpiModeFlags::SPI_NO_CS),
+            loop_back: mode.contains(SpiModeFlags::SPI_LOOP),
+            max_speed_hz,
+            bits_per_word,
+        }
+    }
+
+    /// Query the configuration of `spidev`.
+    fn read(spidev: &spidev::Spidev) -> io::Result<Self> {
+        use spidev::spidevioctl;
+        use std::os::unix::io::AsRawFd;
+
+        let fd = spidev.as_raw_fd();
+        let mode = spidevioctl::get_mode(fd)?;
+        let max_speed_hz = spidevioctl::get_max_speed_hz(fd)?;
+        let bits_per_word = spidevioctl::get_bits_per_word(fd)?;
+        // The kernel reports 0 for the default word size of 8 bits
+        let bits_per_word = if bits_per_word == 0 { 8 } else { bits_per_word };
+        Ok(SpiConfig::from_mode(mode, max_speed_hz, bits_per_word))
+    }
+
+    /// `mode` with the flags described by the snapshot set accordingly, and all other flags
+    /// unchanged
+    fn apply_to_mode(&self, mode: u32) -> u32 {
+        use spidev::SpiModeFlags;
+
+        let flags = [
+            (SpiModeFlags::SPI_CPOL, self.cpol),
+            (SpiModeFlags::SPI_CPHA, self.cpha),
+            (SpiModeFlags::SPI_CS_HIGH, self.cs_active_high),
+            (SpiModeFlags::SPI_LSB_FIRST, self.lsb_first),
+            (SpiModeFlags::SPI_3WIRE, self.three_wire),
+            (SpiModeFlags::SPI_NO_CS, self.no_cs),
+            (SpiModeFlags::SPI_LOOP, self.loop_back),
+        ];
+        flags.iter().fold(mode, |mode, &(flag, set)| {
+            let flag = flag.bits();
+            if set {
+                mode | flag
+            } else {
+                mode & !flag
+            }
+        })
+    }
+
+    /// Configure `spidev` as described by the snapshot, keeping the other mode flags, and
+    /// return the mode flags set.
+    fn write(&self, spidev: &spidev::Spidev) -> io::Result<u32> {
+        use spidev::spidevioctl;
+        use std::os::unix::io::AsRawFd;
+
+        let fd = spidev.as_raw_fd();
+        let mode = self.apply_to_mode(read_mode(fd)?);
+        write_mode(fd, mode)?;
+        spidevioctl::set_max_speed_hz(fd, self.max_speed_hz)?;
+        spidevioctl::set_bits_per_word(fd, self.bits_per_word)?;
+        Ok(mode)
+    }
+}
 
 impl SpidevDevice {
+    /// Wrap an already opened [`spidev::Spidev`].
+    pub fn new(spidev: spidev::Spidev) -> Self {
+        SpidevDevice(spidev, DeviceSettings::default())
+    }
+
     /// See [`spidev::Spidev::open`] for details.
     ///
     /// The provided `path` is for the specific device you wish to access.
@@ -76,12 +334,226 @@ impl SpidevDevice {
         P: AsRef<Path>,
     {
         spidev::Spidev::open(path)
-            .map(SpidevDevice)
+            .map(SpidevDevice::new)
             .map_err(|e| e.into())
     }
+
+    /// Open the device on chip select `cs` of the SPI bus number `bus`, i.e.
+    /// `/dev/spidev{bus}.{cs}`, like [`SpidevDevice::open`].
+    ///
+    /// Bus and chip-select numbers are those of the device tree, and found in the paths
+    /// returned by [`list_spi_devices`]. If the device node doesn't exist, the error is of
+    /// kind [`io::ErrorKind::NotFound`] and names the path: the SPI controller may need to be
+    /// enabled, e.g. with a device tree overlay, and the device bound to the `spidev` driver.
+    ///
+    /// [`list_spi_devices`]: crate::list_spi_devices
+    pub fn from_bus_cs(bus: u32, cs: u32) -> Result<Self, SPIError> {
+        let path = format!("/dev/spidev{}.{}", bus, cs);
+        spidev::Spidev::open(&path)
+            .map(SpidevDevice::new)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => SPIError::from(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "no SPI device on bus {}, chip select {}: {} does not exist, \
+                         check that the controller is enabled and the device bound to spidev",
+                        bus, cs, path
+                    ),
+                )),
+                _ => SPIError::from(err),
+            })
+    }
+
+    /// Open the device at `path` for reading only, e.g. when only read permission is granted.
+    ///
+    /// Transactions, as well as configuration, go through ioctls, which spidev accepts on
+    /// files opened with any access mode, so every operation of the device works, writes
+    /// included: the access mode only determines which permission opening needs. Only
+    /// plain `write` calls on the inner file fail, with `EBADF`.
+    pub fn open_read_only<P>(path: P) -> Result<Self, SPIError>
+    where
+        P: AsRef<Path>,
+    {
+        open_file(path.as_ref(), true, false).map(SpidevDevice::new)
+    }
+
+    /// Open the device at `path` for writing only, e.g. when only write permission is granted,
+    /// as for an output-only device such as an LED strip.
+    ///
+    /// As for [`SpidevDevice::open_read_only`], every operation of the device works, reads
+    /// included, and only plain `read` calls on the inner file fail, with `EBADF`.
+    pub fn open_write_only<P>(path: P) -> Result<Self, SPIError>
+    where
+        P: AsRef<Path>,
+    {
+        open_file(path.as_ref(), false, true).map(SpidevDevice::new)
+    }
+
+    /// Set the delay the controller inserts between the words of each transfer of a
+    /// [transaction], in microseconds. `0`, the default, disables it.
+    ///
+    /// Some slow, microcontroller-based devices need this time to handle each word.
+    ///
+    /// The `word_delay_usecs` transfer field was added in Linux 5.3; older kernels ignore it,
+    /// so transfers are then sent without delay. Controllers whose driver doesn't implement
+    /// word delays ignore it as well.
+    ///
+    /// [transaction]: embedded_hal::spi::SpiDevice::transaction
+    pub fn set_word_delay(&mut self, us: u8) {
+        self.1.word_delay_us = us;
+    }
+
+    /// Select whether CS is active high, rather than the usual active low.
+    ///
+    /// Only the [`SPI_CS_HIGH`] flag of the mode is changed: all 32 bits of the mode are read
+    /// from the kernel, and written back with just that flag updated, so that the clock
+    /// polarity and phase and other flags, including the dual, quad and octal transfer ones,
+    /// are kept, unlike when [configuring] the inner [`spidev::Spidev`] with a whole new mode.
+    ///
+    /// [`SPI_CS_HIGH`]: spidev::SpiModeFlags::SPI_CS_HIGH
+    /// [configuring]: spidev::Spidev::configure
+    pub fn set_cs_active_high(&mut self, active_high: bool) -> Result<(), SPIError> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = self.0.as_raw_fd();
+        let mode = with_cs_high(read_mode(fd)?, active_high);
+        write_mode(fd, mode)?;
+        self.1.mode = Some(mode);
+        Ok(())
+    }
+
+    /// Run `f` with the clock of the device set to `hz`, then set it back to what it was.
+    ///
+    /// This suits devices needing a different clock for some operations, such as a slow
+    /// initialization sequence. The previous speed is restored when `f` returns, and also if
+    /// it panics, through a guard. Since the guard cannot report errors, a failure to restore
+    /// the speed is ignored; it could only happen if the device went away.
+    ///
+    /// The speed is the `max_speed_hz` of the device, so other users of the same spidev
+    /// device, e.g. clones of the file descriptor, also see the change while `f` runs.
+    pub fn with_speed<R>(
+        &mut self,
+        hz: u32,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> Result<R, SPIError> {
+        use spidev::spidevioctl;
+        use std::os::unix::io::AsRawFd;
+
+        let fd = self.0.as_raw_fd();
+        let previous = spidevioctl::get_max_speed_hz(fd)?;
+        spidevioctl::set_max_speed_hz(fd, hz)?;
+        let mut guard = Restore::new(self, move |dev: &mut Self| {
+            let _ = spidevioctl::set_max_speed_hz(dev.0.as_raw_fd(), previous);
+        });
+        Ok(f(&mut guard))
+    }
+
+    /// Set the clock speed of the device, in Hz.
+    ///
+    /// Only the speed is changed, with its own ioctl, so the mode and word size are kept,
+    /// unlike when [configuring] the inner [`spidev::Spidev`] with [`spidev::SpidevOptions`]
+    /// that leave them out. The controller may round the speed down to one it can generate,
+    /// which the kernel doesn't report: [`SpidevDevice::config`] returns `hz` unchanged.
+    /// See [`SpidevDevice::with_speed`] to change it for a few operations only.
+    ///
+    /// [configuring]: spidev::Spidev::configure
+    pub fn set_max_speed_hz(&mut self, hz: u32) -> Result<(), SPIError> {
+        use std::os::unix::io::AsRawFd;
+
+        spidev::spidevioctl::set_max_speed_hz(self.0.as_raw_fd(), hz)?;
+        Ok(())
+    }
+
+    /// Set the number of bits per word, between 1 and 32, 8 being the default.
+    ///
+    /// Only the word size is changed, with its own ioctl, so the mode and speed are kept.
+    /// spidev packs words of up to 8 bits in one byte, words of 9 to 16 bits in two bytes and
+    /// larger words in four bytes, in native byte order, so with words of more than 8 bits,
+    /// the kernel rejects transactions whose buffers don't hold whole words. Sizes that the
+    /// controller doesn't support are rejected as well.
+    pub fn set_bits_per_word(&mut self, bits: u8) -> Result<(), SPIError> {
+        use std::os::unix::io::AsRawFd;
+
+        check_bits_per_word(bits)?;
+        spidev::spidevioctl::set_bits_per_word(self.0.as_raw_fd(), bits)?;
+        Ok(())
+    }
+
+    /// Open every chip select of the SPI bus number `bus`, e.g. `/dev/spidev0.0` and
+    /// `/dev/spidev0.1` for bus 0, ordered by chip-select number.
+    ///
+    /// The devices are found with [`list_spi_devices`]. They all share the bus, and the
+    /// kernel serializes their transfers, so each can be handed to a different driver, or
+    /// thread. An error opening any of them is returned with its path; an empty list means
+    /// that the bus has no spidev devices.
+    ///
+    /// [`list_spi_devices`]: crate::list_spi_devices
+    pub fn open_all_on_bus(bus: u32) -> io::Result<Vec<SpidevDevice>> {
+        crate::list_spi_devices()?
+            .into_iter()
+            .filter(|dev| is_on_bus(&dev.path, bus))
+            .map(|dev| {
+                spidev::Spidev::open(&dev.path)
+                    .map(SpidevDevice::new)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dev.path.display(), e)))
+            })
+            .collect()
+    }
+
+    /// Read the current configuration of the device from the kernel.
+    pub fn config(&self) -> Result<SpiConfig, SPIError> {
+        SpiConfig::read(&self.0).map_err(SPIError::from)
+    }
+
+    /// Set the byte clocked out while reading, i.e. during [`Read`] operations and past the
+    /// end of the write buffer of [`Transfer`] operations.
+    ///
+    /// By default, reads don't send any data, and most controllers then clock out `0x00`.
+    /// Some devices, such as flash memories during their read phase, expect `0xFF` instead.
+    /// Once a dummy byte is set, reads are sent as full-duplex transfers of the dummy byte,
+    /// without allocating. In 3-wire mode, transactions with [`Read`] operations then fail
+    /// with an error of kind [`io::ErrorKind::InvalidInput`] without sending anything.
+    ///
+    /// [`Read`]: embedded_hal::spi::Operation::Read
+    /// [`Transfer`]: embedded_hal::spi::Operation::Transfer
+    pub fn set_dummy_byte(&mut self, dummy_byte: u8) {
+        self.1.dummy_byte = Some(dummy_byte);
+    }
+
+    /// Total number of bytes written by successful transactions, from the write buffers of the
+    /// operations.
+    ///
+    /// The counters are best-effort statistics, kept by this handle only at the cost of a few
+    /// additions per transfer. They count what was requested, not what the controller clocked,
+    /// so padding and delays are not included, and they do not account for other users of the
+    /// bus.
+    pub fn bytes_written(&self) -> u64 {
+        self.1.stats.written
+    }
+
+    /// Total number of bytes read by successful transactions, into the read buffers of the
+    /// operations. See [`SpidevDevice::bytes_written`].
+    pub fn bytes_read(&self) -> u64 {
+        self.1.stats.read
+    }
+
+    /// Number of successful transactions. See [`SpidevDevice::bytes_written`].
+    pub fn transfer_count(&self) -> u64 {
+        self.1.stats.transfers
+    }
+
+    /// Reset the transfer counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.1.stats = Stats::default();
+    }
 }
 
 impl SpidevBus {
+    /// Wrap an already opened [`spidev::Spidev`].
+    pub fn new(spidev: spidev::Spidev) -> Self {
+        SpidevBus(spidev, BusSettings::default())
+    }
+
     /// See [`spidev::Spidev::open`] for details.
     ///
     /// The provided `path` must be the _only_ device in use on its bus,
@@ -93,9 +565,248 @@ impl SpidevBus {
         P: AsRef<Path>,
     {
         spidev::Spidev::open(path)
-            .map(SpidevBus)
+            .map(SpidevBus::new)
             .map_err(|e| e.into())
     }
+
+    /// Set the byte clocked out when [`SpiBus::transfer`] reads more words than it writes.
+    ///
+    /// Defaults to `0x00`. Some devices expect `0xFF` to be sent while they are being read.
+    ///
+    /// [`SpiBus::transfer`]: embedded_hal::spi::SpiBus::transfer
+    pub fn set_dummy_byte(&mut self, dummy_byte: u8) {
+        self.1.dummy_byte = dummy_byte;
+    }
+
+    /// Set the largest number of bytes sent to the kernel at once.
+    ///
+    /// spidev rejects messages larger than its buffer, so reads, writes and transfers of
+    /// [`SpiBus`] longer than this are split into several kernel calls. CS is not affected,
+    /// being driven by the user, but the clock may pause between chunks.
+    ///
+    /// Defaults to the `bufsiz` parameter of the spidev module as read from sysfs when
+    /// opening the bus, or to 4096, the kernel default, if that fails, as it may in containers.
+    /// Set it explicitly for deterministic behaviour.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is zero.
+    ///
+    /// [`SpiBus`]: embedded_hal::spi::SpiBus
+    pub fn set_max_transfer_size(&mut self, bytes: usize) {
+        assert!(bytes > 0, "the maximum transfer size must not be zero");
+        self.1.max_transfer_size = bytes;
+    }
+
+    /// Set the number of bits per word, between 1 and 32, 8 being the default.
+    ///
+    /// spidev packs words of up to 8 bits in one byte, words of 9 to 16 bits in two bytes and
+    /// larger words in four bytes, in native byte order, using the low bits of each. With
+    /// words of more than 8 bits, the buffers of [`SpiBus`] operations must therefore hold
+    /// whole words, or the operation fails with an error of kind
+    /// [`io::ErrorKind::InvalidInput`] without sending anything, and transfers longer than
+    /// the [maximum transfer size](SpidevBus::set_max_transfer_size) are only split between
+    /// words. Sizes that the controller doesn't support are rejected by the kernel.
+    ///
+    /// [`SpiBus`]: embedded_hal::spi::SpiBus
+    pub fn set_bits_per_word(&mut self, bits: u8) -> Result<(), SPIError> {
+        use std::os::unix::io::AsRawFd;
+
+        check_bits_per_word(bits)?;
+        spidev::spidevioctl::set_bits_per_word(self.0.as_raw_fd(), bits)?;
+        self.1.word_bytes = word_bytes(bits);
+        Ok(())
+    }
+
+    /// The largest number of bytes sent to the kernel at once, see
+    /// [`SpidevBus::set_max_transfer_size`].
+    pub fn max_transfer_size(&self) -> usize {
+        self.1.max_transfer_size
+    }
+
+    /// Use `scratch` as the staging buffer of [`SpiBus::transfer`].
+    ///
+    /// When a transfer reads more bytes than it writes, the write data is padded with the
+    /// [dummy byte](SpidevBus::set_dummy_byte) in a staging buffer, which is kept and reused
+    /// by later transfers, growing as needed up to the
+    /// [maximum transfer size](SpidevBus::set_max_transfer_size). Providing a buffer with
+    /// enough capacity upfront avoids any allocation in the transfer path, e.g. for periodic
+    /// display updates. Other bus operations don't need staging and never allocate.
+    ///
+    /// [`SpidevDevice`] has no counterpart, as it never stages write data: transfers with
+    /// buffers of unequal length are split into two kernel transfers within the transaction.
+    ///
+    /// No particular alignment is needed: spidev copies data between user buffers and its
+    /// own kernel buffer, which is what the controller accesses, possibly by DMA.
+    ///
+    /// [`SpiBus::transfer`]: embedded_hal::spi::SpiBus::transfer
+    pub fn with_scratch(mut self, scratch: Vec<u8>) -> Self {
+        self.1.scratch = scratch;
+        self
+    }
+
+    /// Read the current configuration of the bus from the kernel.
+    pub fn config(&self) -> Result<SpiConfig, SPIError> {
+        SpiConfig::read(&self.0).map_err(SPIError::from)
+    }
+
+    /// Total number of bytes written by successful bus operations (reads, writes and
+    /// transfers), from the write buffers of the operations.
+    ///
+    /// The counters are best-effort statistics, kept by this handle only at the cost of a few
+    /// additions per transfer. They count what was requested, not what the controller clocked,
+    /// so padding and delays are not included, and they do not account for other users of the
+    /// bus.
+    pub fn bytes_written(&self) -> u64 {
+        self.1.stats.written
+    }
+
+    /// Total number of bytes read by successful bus operations (reads, writes and transfers),
+    /// into the read buffers of the operations. See [`SpidevBus::bytes_written`].
+    pub fn bytes_read(&self) -> u64 {
+        self.1.stats.read
+    }
+
+    /// Number of successful bus operations (reads, writes and transfers). See
+    /// [`SpidevBus::bytes_written`].
+    pub fn transfer_count(&self) -> u64 {
+        self.1.stats.transfers
+    }
+
+    /// Reset the transfer counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.1.stats = Stats::default();
+    }
+}
+
+/// Scoped GPIO chip-select for devices sharing a [`SpidevBus`]
+///
+/// Creating the guard asserts (drives low) the CS pin, and dropping it flushes the bus and
+/// deasserts (drives high) the pin again, even on early return or panic. The guard
+/// dereferences to the bus, so the device can be accessed through it while selected.
+///
+/// This is a lightweight alternative to the [`embedded-hal-bus`] crate for software CS pins.
+/// Errors while deasserting CS on drop are ignored; use [`CsGuard::release`] to observe them.
+///
+/// ```no_run
+/// # #[cfg(feature = "gpio_cdev")] {
+/// use embedded_hal::spi::SpiBus;
+/// use linux_embedded_hal::{CdevPin, CsGuard, SpidevBus};
+/// # fn cs_pin() -> CdevPin { unimplemented!() }
+///
+/// let mut bus = SpidevBus::open("/dev/spidev0.0").unwrap();
+/// let mut cs = cs_pin();
+/// {
+///     let mut selected = CsGuard::new(&mut bus, &mut cs).unwrap();
+///     selected.write(&[0x9F]).unwrap();
+/// } // CS is deasserted here
+/// # }
+/// ```
+///
+/// [`embedded-hal-bus`]: https://docs.rs/embedded-hal-bus/
+pub struct CsGuard<'a, B, P>
+where
+    B: embedded_hal::spi::SpiBus,
+    P: embedded_hal::digital::OutputPin,
+{
+    bus: &'a mut B,
+    cs: &'a mut P,
+}
+
+impl<'a, B, P> CsGuard<'a, B, P>
+where
+    B: embedded_hal::spi::SpiBus,
+    P: embedded_hal::digital::OutputPin,
+{
+    /// Assert `cs` and give access to `bus` until the guard is dropped
+    pub fn new(bus: &'a mut B, cs: &'a mut P) -> Result<Self, P::Error> {
+        cs.set_low()?;
+        Ok(CsGuard { bus, cs })
+    }
+
+    /// Flush the bus and deassert CS, returning any error encountered
+    pub fn release(self) -> Result<(), CsGuardError<B::Error, P::Error>> {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        let flushed = this.bus.flush().map_err(CsGuardError::Spi);
+        let deasserted = this.cs.set_high().map_err(CsGuardError::Pin);
+        flushed.and(deasserted)
+    }
+}
+
+impl<B, P> ops::Deref for CsGuard<'_, B, P>
+where
+    B: embedded_hal::spi::SpiBus,
+    P: embedded_hal::digital::OutputPin,
+{
+    type Target = B;
+
+    fn deref(&self) -> &Self::Target {
+        self.bus
+    }
+}
+
+impl<B, P> ops::DerefMut for CsGuard<'_, B, P>
+where
+    B: embedded_hal::spi::SpiBus,
+    P: embedded_hal::digital::OutputPin,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.bus
+    }
+}
+
+impl<B, P> Drop for CsGuard<'_, B, P>
+where
+    B: embedded_hal::spi::SpiBus,
+    P: embedded_hal::digital::OutputPin,
+{
+    fn drop(&mut self) {
+        let _ = self.bus.flush();
+        let _ = self.cs.set_high();
+    }
+}
+
+/// Error returned by [`CsGuard::release`]
+#[derive(Debug)]
+pub enum CsGuardError<S, P> {
+    /// Flushing the bus failed
+    Spi(S),
+    /// Deasserting the CS pin failed
+    Pin(P),
+}
+
+impl<S: fmt::Display, P: fmt::Display> fmt::Display for CsGuardError<S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsGuardError::Spi(err) => write!(f, "{}", err),
+            CsGuardError::Pin(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<S, P> std::error::Error for CsGuardError<S, P>
+where
+    S: std::error::Error + 'static,
+    P: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CsGuardError::Spi(err) => Some(err),
+            CsGuardError::Pin(err) => Some(err),
+        }
+    }
+}
+
+impl From<spidev::Spidev> for SpidevDevice {
+    fn from(spidev: spidev::Spidev) -> Self {
+        SpidevDevice::new(spidev)
+    }
+}
+
+impl From<spidev::Spidev> for SpidevBus {
+    fn from(spidev: spidev::Spidev) -> Self {
+        SpidevBus::new(spidev)
+    }
 }
 
 impl ops::Deref for SpidevDevice {
@@ -108,6 +819,8 @@ impl ops::Deref for SpidevDevice {
 
 impl ops::DerefMut for SpidevDevice {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        // The caller may reconfigure the device
+        self.1.mode = None;
         &mut self.0
     }
 }
@@ -131,8 +844,9 @@ mod embedded_hal_impl {
     use embedded_hal::spi::ErrorType;
     use embedded_hal::spi::{Operation as SpiOperation, SpiBus, SpiDevice};
     use spidev::SpidevTransfer;
-    use std::convert::TryInto;
-    use std::io::{Read, Write};
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
 
     impl ErrorType for SpidevDevice {
         type Error = SPIError;
@@ -143,109 +857,618 @@ mod embedded_hal_impl {
     }
 
     impl SpiBus<u8> for SpidevBus {
+        /// Read `words.len()` bytes from the bus. [Read more][read]
+        ///
+        /// Each read of the device file is a receive-only SPI transfer, so the controller
+        /// clocks the bus for every byte requested, typically sending zeros. spidev returns
+        /// either all of them or an error; a short read is reported as an
+        /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error stating how many bytes
+        /// arrived, rather than completed by another transfer.
+        ///
+        /// [read]: SpiBus::read
         fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
-            self.0.read_exact(words).map_err(|err| SPIError { err })
+            self.1.check_words(words.len())?;
+            for chunk in words.chunks_mut(self.1.chunk_size()) {
+                read_transfer(&mut self.0, chunk).map_err(|err| SPIError { err })?;
+            }
+            self.1.stats.record(0, words.len());
+            Ok(())
         }
 
         fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
-            self.0.write_all(words).map_err(|err| SPIError { err })
+            self.1.check_words(words.len())?;
+            for chunk in words.chunks(self.1.chunk_size()) {
+                self.0.write_all(chunk).map_err(|err| SPIError { err })?;
+            }
+            self.1.stats.record(words.len(), 0);
+            Ok(())
         }
 
         fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
-            let read_len = read.len();
-            match read_len.cmp(&write.len()) {
-                Ordering::Less => self.0.transfer_multiple(&mut [
-                    SpidevTransfer::read_write(&write[..read_len], read),
-                    SpidevTransfer::write(&write[read_len..]),
-                ]),
-                Ordering::Equal => self
-                    .0
-                    .transfer(&mut SpidevTransfer::read_write(write, read)),
-                Ordering::Greater => {
-                    let (read1, read2) = read.split_at_mut(write.len());
-                    self.0.transfer_multiple(&mut [
-                        SpidevTransfer::read_write(write, read1),
-                        SpidevTransfer::read(read2),
-                    ])
-                }
-            }
-            .map_err(|err| SPIError { err })
+            self.transfer_with_delay(read, write, 0)
         }
 
         fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
-            let tx = words.to_owned();
-            self.0
-                .transfer(&mut SpidevTransfer::read_write(&tx, words))
-                .map_err(|err| SPIError { err })
+            let len = words.len();
+            self.1.check_words(len)?;
+            for chunk in words.chunks_mut(self.1.chunk_size()) {
+                // spidev supports using the same buffer for both directions, so
+                // no copy of `words` is needed.
+                let mut transfer = SpidevTransfer::read_write_in_place(chunk);
+                retry_interrupted(|| self.0.transfer(&mut transfer))
+                    .map_err(|err| SPIError { err })?;
+            }
+            self.1.stats.record(len, len);
+            Ok(())
         }
 
+        /// Does nothing: every spidev read, write and transfer ioctl only returns once the
+        /// kernel has clocked all of its words, so there is never anything left to flush.
         fn flush(&mut self) -> Result<(), Self::Error> {
-            self.0.flush().map_err(|err| SPIError { err })
+            Ok(())
+        }
+    }
+
+    /// Map `operations` to spidev transfers configured after `settings`, handing them to
+    /// `push` in order.
+    fn for_each_transfer<'a>(
+        operations: &'a mut [SpiOperation<'_, u8>],
+        settings: &DeviceSettings,
+        mut push: impl FnMut(SpidevTransfer<'a, 'a>) -> Result<(), SPIError>,
+    ) -> Result<(), SPIError> {
+        let word_delay = word_delay_pad(settings.word_delay_us);
+        let mut push = |mut transfer: SpidevTransfer<'a, 'a>| {
+            transfer.pad = word_delay;
+            push(transfer)
+        };
+        // Reads clock out the dummy byte by sending it from the read buffer itself
+        let dummy_byte = settings.dummy_byte;
+        let receive = |buf: &'a mut [u8]| match dummy_byte {
+            Some(byte) => {
+                buf.fill(byte);
+                SpidevTransfer::read_write_in_place(buf)
+            }
+            None => SpidevTransfer::read(buf),
+        };
+        for op in operations {
+            match op {
+                SpiOperation::Read(buf) => push(receive(buf))?,
+                SpiOperation::Write(buf) => push(SpidevTransfer::write(buf))?,
+                SpiOperation::Transfer(read, write) => match read.len().cmp(&write.len()) {
+                    Ordering::Less => {
+                        let n = read.len();
+                        push(SpidevTransfer::read_write(&write[..n], read))?;
+                        push(SpidevTransfer::write(&write[n..]))?;
+                    }
+                    Ordering::Equal => push(SpidevTransfer::read_write(write, read))?,
+                    Ordering::Greater => {
+                        let (read1, read2) = read.split_at_mut(write.len());
+                        push(SpidevTransfer::read_write(write, read1))?;
+                        push(receive(read2))?;
+                    }
+                },
+                SpiOperation::TransferInPlace(buf) => {
+                    push(SpidevTransfer::read_write_in_place(buf))?;
+                }
+                SpiOperation::DelayNs(ns) => {
+                    for us in delay_chunks_us(*ns) {
+                        push(SpidevTransfer::delay(us))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    impl SpidevDevice {
+        /// Perform a transaction against the device without allocating.
+        ///
+        /// This behaves like [`SpiDevice::transaction`], but the kernel transfers are built in
+        /// a stack array holding at most `N` of them instead of on the heap. Each operation
+        /// takes one transfer, except for [`Transfer`] operations with buffers of unequal
+        /// length, which take two, and [delays][delay] longer than 65535 microseconds, which
+        /// take one per started 65535 microseconds.
+        ///
+        /// If the operations need more than `N` transfers, nothing is sent and an error of
+        /// kind [`io::ErrorKind::InvalidInput`] is returned.
+        ///
+        /// [`Transfer`]: SpiOperation::Transfer
+        /// [delay]: SpiOperation::DelayNs
+        pub fn transaction_in<const N: usize>(
+            &mut self,
+            operations: &mut [SpiOperation<'_, u8>],
+        ) -> Result<(), SPIError> {
+            check_duplex(self, operations)?;
+            let (written, read) = operation_lengths(operations);
+            let mut transfers: [SpidevTransfer; N] = core::array::from_fn(|_| Default::default());
+            let mut len = 0;
+            for_each_transfer(operations, &self.1, |transfer| {
+                let slot = transfers.get_mut(len).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("transaction needs more than {} transfers", N),
+                    )
+                })?;
+                *slot = transfer;
+                len += 1;
+                Ok(())
+            })?;
+            retry_interrupted(|| self.0.transfer_multiple(&mut transfers[..len]))
+                .map_err(|err| SPIError { err })?;
+            self.1.stats.record(written, read);
+            Ok(())
+        }
+    }
+
+    impl SpidevBus {
+        /// Perform a [`SpiBus::transfer`], with the controller waiting `delay_us` microseconds
+        /// after each spidev transfer it is made of.
+        ///
+        /// Some devices need settling time in the middle of a transaction, e.g. between a
+        /// command and the following data. When `write` is longer than `read`, the transfer
+        /// is sent as a full-duplex transfer followed by a write-only one, and the delay
+        /// separates them. Transfers longer than the
+        /// [maximum transfer size](SpidevBus::set_max_transfer_size) are split as well, with
+        /// the delay between the parts. The last transfer is also followed by the delay,
+        /// before the call returns. When `read` is at least as long as `write`, each part is a
+        /// single transfer, so the delay only follows it.
+        ///
+        /// The parts of a split transfer are separate messages, between which spidev's own
+        /// chip select may toggle. This is therefore meant for a `SpidevBus` whose chip
+        /// select is a GPIO driven by the caller, which stays asserted throughout; a
+        /// [`SpidevDevice`] can already insert delays anywhere in a transaction with
+        /// [delay operations](SpiOperation::DelayNs).
+        pub fn transfer_with_delay(
+            &mut self,
+            read: &mut [u8],
+            write: &[u8],
+            delay_us: u16,
+        ) -> Result<(), SPIError> {
+            let (read_len, write_len) = (read.len(), write.len());
+            self.1.check_words(read_len)?;
+            self.1.check_words(write_len)?;
+            for range in chunk_ranges(read_len.max(write_len), self.1.chunk_size()) {
+                let write = &write[range.start.min(write_len)..range.end.min(write_len)];
+                let read = &mut read[range.start.min(read_len)..range.end.min(read_len)];
+                let len = range.len();
+                match read.len().cmp(&write.len()) {
+                    Ordering::Less if read.is_empty() => {
+                        let mut transfer = SpidevTransfer::write(write);
+                        transfer.delay_usecs = delay_us;
+                        retry_interrupted(|| self.0.transfer(&mut transfer))
+                    }
+                    Ordering::Less => {
+                        let n = read.len();
+                        let mut transfers = [
+                            SpidevTransfer::read_write(&write[..n], read),
+                            SpidevTransfer::write(&write[n..]),
+                        ];
+                        for transfer in &mut transfers {
+                            transfer.delay_usecs = delay_us;
+                        }
+                        retry_interrupted(|| self.0.transfer_multiple(&mut transfers))
+                    }
+                    Ordering::Equal => {
+                        let mut transfer = SpidevTransfer::read_write(write, read);
+                        transfer.delay_usecs = delay_us;
+                        retry_interrupted(|| self.0.transfer(&mut transfer))
+                    }
+                    Ordering::Greater => {
+                        // Use a single transfer so the kernel can't insert a gap
+                        // between the written and the read-only part.
+                        let (spidev, settings) = (&mut self.0, &mut self.1);
+                        let tx = padded(&mut settings.scratch, write, len, settings.dummy_byte);
+                        let mut transfer = SpidevTransfer::read_write(tx, read);
+                        transfer.delay_usecs = delay_us;
+                        retry_interrupted(|| spidev.transfer(&mut transfer))
+                    }
+                }
+                .map_err(|err| SPIError { err })?;
+            }
+            self.1.stats.record(write_len, read_len);
+            Ok(())
+        }
+
+        /// Check whether data sent on MOSI is received back on MISO.
+        ///
+        /// Transfers a known pattern and returns whether exactly the same data was received,
+        /// which helps telling wiring or driver problems apart from device problems during
+        /// bring-up.
+        ///
+        /// Requires MOSI and MISO to be shorted together. The pattern is clocked out to
+        /// whatever is connected to the bus, which may confuse or damage devices, so only use
+        /// this on a dedicated test rig.
+        pub fn loopback_test(&mut self) -> Result<bool, SPIError> {
+            let mut read = [0; LOOPBACK_PATTERN.len()];
+            SpiBus::transfer(self, &mut read, &LOOPBACK_PATTERN)?;
+            Ok(read == LOOPBACK_PATTERN)
+        }
+    }
+
+    impl SpidevDevice {
+        /// Check whether data sent on MOSI is received back on MISO.
+        ///
+        /// Transfers a known pattern in a single transaction and returns whether exactly the
+        /// same data was received, see [`SpidevBus::loopback_test`].
+        ///
+        /// Requires MOSI and MISO to be shorted together. The pattern is clocked out to
+        /// whatever is connected to the bus, which may confuse or damage devices, so only use
+        /// this on a dedicated test rig.
+        pub fn loopback_test(&mut self) -> Result<bool, SPIError> {
+            let mut read = [0; LOOPBACK_PATTERN.len()];
+            SpiDevice::transaction(
+                self,
+                &mut [SpiOperation::Transfer(&mut read, &LOOPBACK_PATTERN)],
+            )?;
+            Ok(read == LOOPBACK_PATTERN)
+        }
+
+        /// Perform a transaction, breaking it up around [delay operations][delay].
+        ///
+        /// With [`SpiDevice::transaction`], delays are performed by the kernel within a single
+        /// message, so CS stays asserted during them. Here instead, the operations between two
+        /// delays are sent as a message of their own, and each delay is a real
+        /// [`thread::sleep`] between messages. CS is therefore deasserted during delays, which
+        /// some devices require, e.g. to start an internal operation. Delays are also not
+        /// limited to the kernel's 65535 microseconds per transfer, but they are subject to
+        /// scheduling latency, and other users of the bus may access it during a delay.
+        ///
+        /// [delay]: SpiOperation::DelayNs
+        pub fn transaction_with_real_delays(
+            &mut self,
+            operations: &mut [SpiOperation<'_, u8>],
+        ) -> Result<(), SPIError> {
+            let mut rest = operations;
+            while !rest.is_empty() {
+                let split = rest
+                    .iter()
+                    .position(|op| matches!(op, SpiOperation::DelayNs(_)))
+                    .unwrap_or(rest.len());
+                let (group, tail) = std::mem::take(&mut rest).split_at_mut(split);
+                if !group.is_empty() {
+                    SpiDevice::transaction(self, group)?;
+                }
+                // `tail` is either empty or starts with the delay that ended `group`
+                if let Some((SpiOperation::DelayNs(ns), tail)) = tail.split_first_mut() {
+                    thread::sleep(Duration::from_nanos((*ns).into()));
+                    rest = tail;
+                }
+            }
+            Ok(())
+        }
+
+        /// Perform a transaction, toggling CS after the operations flagged in `cs_changes`.
+        ///
+        /// This behaves like [`SpiDevice::transaction`], except that CS is briefly deasserted
+        /// after each operation whose flag in `cs_changes` is `true`, using the `cs_change`
+        /// flag of the kernel transfers. Some devices need such a CS pulse, e.g. to latch a
+        /// command before its data phase. Other users of the bus can't access it during the
+        /// pulse, as the operations still form a single message. Flagging the last operation
+        /// asks the controller to keep CS asserted after the transaction instead, which not all
+        /// controllers honour, and which stalls other devices on the bus until the next one.
+        ///
+        /// `cs_changes` must hold one flag per operation, otherwise nothing is sent and an error
+        /// of kind [`io::ErrorKind::InvalidInput`] is returned.
+        ///
+        /// There is no counterpart on [`SpidevBus`], whose CS pins are driven by the user
+        /// between bus operations.
+        pub fn transaction_with_cs_changes(
+            &mut self,
+            operations: &mut [SpiOperation<'_, u8>],
+            cs_changes: &[bool],
+        ) -> Result<(), SPIError> {
+            if cs_changes.len() != operations.len() {
+                return Err(SPIError::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "one CS change flag is needed per operation",
+                )));
+            }
+            check_duplex(self, operations)?;
+            let (written, read) = operation_lengths(operations);
+            let mut transfers = Vec::with_capacity(operations.len());
+            for (operation, &cs_change) in operations.iter_mut().zip(cs_changes) {
+                let start = transfers.len();
+                for_each_transfer(std::slice::from_mut(operation), &self.1, |transfer| {
+                    transfers.push(transfer);
+                    Ok(())
+                })?;
+                // Operations split into several transfers toggle CS after the last one only
+                match transfers[start..].last_mut() {
+                    Some(last) if cs_change => last.cs_change = 1,
+                    _ => {}
+                }
+            }
+            retry_interrupted(|| self.0.transfer_multiple(&mut transfers))
+                .map_err(|err| SPIError { err })?;
+            self.1.stats.record(written, read);
+            Ok(())
+        }
+    }
+
+    impl SpidevDevice {
+        /// Perform a transaction with the device configured as described by `config`, then
+        /// configure it back as it was.
+        ///
+        /// This suits devices sharing a bus, one after the other, while needing different
+        /// modes, speeds or word sizes: all of them are switched for the transaction only,
+        /// including when it fails. The previous configuration is [read](SpidevDevice::config)
+        /// first, and restored by a guard, which also runs if `config` could only be partly
+        /// applied, or if the transaction panics. Since the guard cannot report errors, a
+        /// failure to restore the configuration is ignored; it could only happen if the device
+        /// went away.
+        ///
+        /// Mode flags not described by [`SpiConfig`], such as the dual, quad and octal
+        /// transfer flags, are kept, as the mode is read and written whole. The configuration
+        /// is that of the spidev device, and is changed with several ioctls, so this is only
+        /// atomic with respect to users of this `SpidevDevice`: other users of the same spidev
+        /// device, e.g. clones of the file descriptor, may see the intermediate
+        /// configurations, and must be kept from using it in the meantime.
+        pub fn transaction_with_config(
+            &mut self,
+            config: SpiConfig,
+            operations: &mut [SpiOperation<'_, u8>],
+        ) -> Result<(), SPIError> {
+            check_bits_per_word(config.bits_per_word)?;
+            let mut guard = swap_config(
+                self,
+                config,
+                |dev: &SpidevDevice| dev.config(),
+                |dev: &mut SpidevDevice, config| {
+                    // Unknown if the configuration is only partly applied
+                    dev.1.mode = None;
+                    dev.1.mode = Some(config.write(&dev.0)?);
+                    Ok(())
+                },
+            )?;
+            SpiDevice::transaction(&mut *guard, operations)
         }
     }
 
     impl SpiDevice for SpidevDevice {
         /// Perform a transaction against the device. [Read more][transaction]
         ///
-        /// [Delay operations][delay] are capped to 65535 microseconds.
+        /// [Delay operations][delay] longer than 65535 microseconds are split into several
+        /// consecutive kernel delay transfers, keeping CS asserted throughout.
+        ///
+        /// In 3-wire mode ([`SPI_3WIRE`]), the single data line can't carry both directions at
+        /// once, so transactions with [`Transfer`] or [`TransferInPlace`] operations, or with
+        /// [`Read`] operations once a [dummy byte](SpidevDevice::set_dummy_byte) is set, fail
+        /// with an error of kind [`io::ErrorKind::InvalidInput`] without sending anything. The mode
+        /// is read from the kernel for the first such transaction, and again after the inner
+        /// [`spidev::Spidev`] has been borrowed mutably through the `SpidevDevice`, e.g. to
+        /// configure it. Changes made through the public field, or by other users of the
+        /// device, are not noticed.
+        ///
+        /// See [`SpidevDevice::transaction_in`] for a variant that does not allocate, and
+        /// [`SpidevDevice::transaction_with_real_delays`] for one deasserting CS during delays.
         ///
         /// [transaction]: SpiDevice::transaction
-        /// [delay]: SpiOperation::DelayUs
+        /// [delay]: SpiOperation::DelayNs
+        /// [`SPI_3WIRE`]: spidev::SpiModeFlags::SPI_3WIRE
+        /// [`Transfer`]: SpiOperation::Transfer
+        /// [`TransferInPlace`]: SpiOperation::TransferInPlace
+        /// [`Read`]: SpiOperation::Read
         fn transaction(
             &mut self,
             operations: &mut [SpiOperation<'_, u8>],
         ) -> Result<(), Self::Error> {
+            check_duplex(self, operations)?;
+            let (written, read) = operation_lengths(operations);
             let mut transfers = Vec::with_capacity(operations.len());
-            for op in operations {
-                match op {
-                    SpiOperation::Read(buf) => transfers.push(SpidevTransfer::read(buf)),
-                    SpiOperation::Write(buf) => transfers.push(SpidevTransfer::write(buf)),
-                    SpiOperation::Transfer(read, write) => match read.len().cmp(&write.len()) {
-                        Ordering::Less => {
-                            let n = read.len();
-                            transfers.push(SpidevTransfer::read_write(&write[..n], read));
-                            transfers.push(SpidevTransfer::write(&write[n..]));
-                        }
-                        Ordering::Equal => transfers.push(SpidevTransfer::read_write(write, read)),
-                        Ordering::Greater => {
-                            let (read1, read2) = read.split_at_mut(write.len());
-                            transfers.push(SpidevTransfer::read_write(write, read1));
-                            transfers.push(SpidevTransfer::read(read2));
-                        }
-                    },
-                    SpiOperation::TransferInPlace(buf) => {
-                        let tx = unsafe {
-                            let p = buf.as_ptr();
-                            std::slice::from_raw_parts(p, buf.len())
-                        };
-                        transfers.push(SpidevTransfer::read_write(tx, buf));
-                    }
-                    SpiOperation::DelayNs(ns) => {
-                        let us = {
-                            if *ns == 0 {
-                                0
-                            } else {
-                                let us = *ns / 1000;
-                                if us == 0 {
-                                    1
-                                } else {
-                                    (us).try_into().unwrap_or(u16::MAX)
-                                }
-                            }
-                        };
-                        transfers.push(SpidevTransfer::delay(us));
-                    }
-                }
-            }
-            self.0
-                .transfer_multiple(&mut transfers)
+            for_each_transfer(operations, &self.1, |transfer| {
+                transfers.push(transfer);
+                Ok(())
+            })?;
+            // The message is complete once the ioctl returns, so unlike on a shared bus,
+            // there is nothing to flush before CS is deasserted.
+            retry_interrupted(|| self.0.transfer_multiple(&mut transfers))
                 .map_err(|err| SPIError { err })?;
-            self.flush()?;
+            self.1.stats.record(written, read);
             Ok(())
         }
     }
 }
 
+/// Fill `buf` with a single read of `reader`, failing if it returns fewer bytes.
+///
+/// Each read of a spidev device is a separate SPI transfer, so a short read must not be
+/// completed with another one, as `read_exact` would do.
+fn read_transfer(reader: &mut impl io::Read, buf: &mut [u8]) -> io::Result<()> {
+    let n = retry_interrupted(|| reader.read(buf))?;
+    if n < buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("SPI read returned {} of {} bytes", n, buf.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// Mutable borrow of a `T` that runs `restore` on it when dropped, including while
+/// unwinding
+struct Restore<'a, T, F: FnMut(&mut T)> {
+    target: &'a mut T,
+    restore: F,
+}
+
+impl<'a, T, F: FnMut(&mut T)> Restore<'a, T, F> {
+    fn new(target: &'a mut T, restore: F) -> Self {
+        Restore { target, restore }
+    }
+}
+
+impl<T, F: FnMut(&mut T)> core::ops::Deref for Restore<'_, T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.target
+    }
+}
+
+impl<T, F: FnMut(&mut T)> core::ops::DerefMut for Restore<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.target
+    }
+}
+
+impl<T, F: FnMut(&mut T)> Drop for Restore<'_, T, F> {
+    fn drop(&mut self) {
+        (self.restore)(self.target)
+    }
+}
+
+/// Save the configuration of `target` with `read`, set it to `config` with `write`, and return
+/// a guard writing the saved configuration back when dropped.
+///
+/// The guard exists before `config` is written, so a partly written `config` is undone as well.
+fn swap_config<'a, T, C, E>(
+    target: &'a mut T,
+    config: C,
+    read: fn(&T) -> Result<C, E>,
+    write: fn(&mut T, &C) -> Result<(), E>,
+) -> Result<Restore<'a, T, impl FnMut(&mut T)>, E> {
+    let previous = read(target)?;
+    let mut guard = Restore::new(target, move |target: &mut T| {
+        let _ = write(target, &previous);
+    });
+    write(&mut guard, &config)?;
+    Ok(guard)
+}
+
+/// `mode` with the `SPI_CS_HIGH` flag set to `active_high`, and all other flags unchanged
+fn with_cs_high(mode: u32, active_high: bool) -> u32 {
+    let cs_high = spidev::SpiModeFlags::SPI_CS_HIGH.bits();
+    if active_high {
+        mode | cs_high
+    } else {
+        mode & !cs_high
+    }
+}
+
+/// Whether `path` is a spidev device node of the bus number `bus`, `spidev<bus>.<cs>`
+fn is_on_bus(path: &Path, bus: u32) -> bool {
+    let prefix = format!("spidev{}.", bus);
+    let cs = path
+        .file_name()
+        .and_then(|name| name.to_str()?.strip_prefix(&prefix));
+    matches!(cs, Some(cs) if !cs.is_empty() && cs.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Fail if `operations` need full duplex while `dev` is in 3-wire mode.
+///
+/// The mode is only queried for transactions that contain full-duplex operations, and only
+/// if it isn't known already.
+fn check_duplex(
+    dev: &mut SpidevDevice,
+    operations: &[embedded_hal::spi::Operation<'_, u8>],
+) -> Result<(), SPIError> {
+    use std::os::unix::io::AsRawFd;
+
+    if !operations.iter().any(|op| is_full_duplex(op, &dev.1)) {
+        return Ok(());
+    }
+    let mode = match dev.1.mode {
+        Some(mode) => mode,
+        None => *dev.1.mode.insert(read_mode(dev.0.as_raw_fd())?),
+    };
+    check_half_duplex(operations, &dev.1, mode).map_err(SPIError::from)
+}
+
+/// Whether `op` sends and receives data at the same time with `settings`
+///
+/// Reads are full duplex once a dummy byte is set, as it is clocked out while reading.
+fn is_full_duplex(op: &embedded_hal::spi::Operation<'_, u8>, settings: &DeviceSettings) -> bool {
+    use embedded_hal::spi::Operation;
+
+    match op {
+        Operation::Transfer(..) | Operation::TransferInPlace(_) => true,
+        Operation::Read(_) => settings.dummy_byte.is_some(),
+        Operation::Write(_) | Operation::DelayNs(_) => false,
+    }
+}
+
+/// Fail if `mode` is 3-wire and `operations` need full duplex with `settings`.
+fn check_half_duplex(
+    operations: &[embedded_hal::spi::Operation<'_, u8>],
+    settings: &DeviceSettings,
+    mode: u32,
+) -> io::Result<()> {
+    let three_wire = mode & spidev::SpiModeFlags::SPI_3WIRE.bits() != 0;
+    if three_wire && operations.iter().any(|op| is_full_duplex(op, settings)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "full-duplex transfer requested in SPI 3-wire mode",
+        ));
+    }
+    Ok(())
+}
+
+/// Total lengths of the write and read buffers of `operations`
+fn operation_lengths(operations: &[embedded_hal::spi::Operation<'_, u8>]) -> (usize, usize) {
+    use embedded_hal::spi::Operation;
+
+    operations
+        .iter()
+        .fold((0, 0), |(written, read), op| match op {
+            Operation::Read(buf) => (written, read + buf.len()),
+            Operation::Write(buf) => (written + buf.len(), read),
+            Operation::Transfer(r, w) => (written + w.len(), read + r.len()),
+            Operation::TransferInPlace(buf) => (written + buf.len(), read + buf.len()),
+            Operation::DelayNs(_) => (written, read),
+        })
+}
+
+/// Split `0..len` into consecutive ranges of at most `max` elements.
+fn chunk_ranges(len: usize, max: usize) -> impl Iterator<Item = std::ops::Range<usize>> {
+    (0..len)
+        .step_by(max)
+        .map(move |start| start..len.min(start + max))
+}
+
+/// Value of the `pad` field of a spidev transfer that sets `word_delay_usecs` to `us`.
+///
+/// spidev exposes the last four bytes of `struct spi_ioc_transfer` as a single `pad` field,
+/// whereas the kernel splits them into `tx_nbits`, `rx_nbits`, `word_delay_usecs` and `pad`.
+fn word_delay_pad(us: u8) -> u32 {
+    u32::from_ne_bytes([0, 0, us, 0])
+}
+
+/// Data sent by the loopback tests: all bits set and clear, alternating bits, and a walking
+/// one, so that stuck, swapped or shifted bits are told apart from a working loopback.
+const LOOPBACK_PATTERN: [u8; 12] = [
+    0x00, 0xFF, 0xA5, 0x5A, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80,
+];
+
+/// Copy `write` into `buf`, resized to `len` bytes, filling the rest with `fill`.
+fn padded<'a>(buf: &'a mut Vec<u8>, write: &[u8], len: usize, fill: u8) -> &'a [u8] {
+    buf.clear();
+    buf.extend_from_slice(write);
+    buf.resize(len, fill);
+    buf
+}
+
+/// Split a delay of `ns` nanoseconds into the microsecond delays of consecutive
+/// [`spidev::SpidevTransfer`]s, each of which is limited to `u16::MAX` microseconds.
+///
+/// Non-zero delays shorter than a microsecond are rounded up to one microsecond.
+fn delay_chunks_us(ns: u32) -> impl Iterator<Item = u16> {
+    let mut us = match ns {
+        0 => 0,
+        ns => (ns / 1000).max(1),
+    };
+    let mut first = true;
+    std::iter::from_fn(move || {
+        if us == 0 && !first {
+            return None;
+        }
+        first = false;
+        let chunk = us.min(u32::from(u16::MAX));
+        us -= chunk;
+        // `chunk` is at most `u16::MAX`
+        Some(chunk as u16)
+    })
+}
+
 /// Error type wrapping [io::Error](io::Error) to implement [embedded_hal::spi::ErrorKind]
 #[derive(Debug)]
 pub struct SPIError {
@@ -265,6 +1488,12 @@ impl From<io::Error> for SPIError {
     }
 }
 
+impl From<SPIError> for io::Error {
+    fn from(err: SPIError) -> Self {
+        err.err
+    }
+}
+
 impl embedded_hal::spi::Error for SPIError {
     #[allow(clippy::match_single_binding)]
     fn kind(&self) -> embedded_hal::spi::ErrorKind {
@@ -285,3 +1514,474 @@ impl std::error::Error for SPIError {
         Some(&self.err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::{ErrorType as PinErrorType, OutputPin};
+    use embedded_hal::spi::{ErrorType, SpiBus};
+    use std::cell::RefCell;
+
+    /// Records bus and CS pin activity into a shared log
+    struct Mock<'a>(&'a RefCell<Vec<&'static str>>);
+
+    impl PinErrorType for Mock<'_> {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Mock<'_> {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("cs low");
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("cs high");
+            Ok(())
+        }
+    }
+
+    impl ErrorType for Mock<'_> {
+        type Error = Infallible;
+    }
+
+    impl SpiBus for Mock<'_> {
+        fn read(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("read");
+            Ok(())
+        }
+
+        fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("write");
+            Ok(())
+        }
+
+        fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("transfer");
+            Ok(())
+        }
+
+        fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("transfer");
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push("flush");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cs_guard_drop() {
+        let log = RefCell::new(Vec::new());
+        let (mut bus, mut cs) = (Mock(&log), Mock(&log));
+        {
+            let mut selected = CsGuard::new(&mut bus, &mut cs).unwrap();
+            selected.write(&[1]).unwrap();
+        }
+        assert_eq!(*log.borrow(), ["cs low", "write", "flush", "cs high"]);
+    }
+
+    #[test]
+    fn test_cs_guard_release() {
+        let log = RefCell::new(Vec::new());
+        let (mut bus, mut cs) = (Mock(&log), Mock(&log));
+        let mut selected = CsGuard::new(&mut bus, &mut cs).unwrap();
+        selected.read(&mut [0]).unwrap();
+        selected.release().unwrap();
+        assert_eq!(*log.borrow(), ["cs low", "read", "flush", "cs high"]);
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut attempts = 0;
+        let res = retry_interrupted(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        let mut attempts = 0;
+        let res: io::Result<()> = retry_interrupted(|| {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::InvalidInput))
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(attempts, 1);
+    }
+
+    /// Ensure that reading more than is written clocks out the dummy byte.
+    #[test]
+    fn test_padded_read_longer_than_write() {
+        let mut buf = Vec::new();
+        assert_eq!(padded(&mut buf, &[1, 2], 5, 0x00), [1, 2, 0, 0, 0]);
+        assert_eq!(padded(&mut buf, &[1, 2], 4, 0xFF), [1, 2, 0xFF, 0xFF]);
+        assert_eq!(padded(&mut buf, &[], 2, 0xFF), [0xFF, 0xFF]);
+        assert_eq!(padded(&mut buf, &[1, 2], 2, 0xFF), [1, 2]);
+        // The buffer is reused rather than reallocated
+        assert!(buf.capacity() >= 5);
+    }
+
+    #[test]
+    fn test_delay_chunks_short() {
+        assert_eq!(delay_chunks_us(0).collect::<Vec<_>>(), [0]);
+        assert_eq!(delay_chunks_us(1).collect::<Vec<_>>(), [1]);
+        assert_eq!(delay_chunks_us(2_500).collect::<Vec<_>>(), [2]);
+        assert_eq!(delay_chunks_us(65_535_000).collect::<Vec<_>>(), [65535]);
+    }
+
+    /// Ensure that delays longer than 65535 µs are not truncated.
+    #[test]
+    fn test_delay_chunks_long() {
+        let chunks: Vec<_> = delay_chunks_us(200_000_000).collect();
+        assert_eq!(chunks, [65535, 65535, 65535, 3395]);
+        let total: u32 = chunks.iter().copied().map(u32::from).sum();
+        assert_eq!(total, 200_000);
+
+        let total: u32 = delay_chunks_us(u32::MAX).map(u32::from).sum();
+        assert_eq!(total, u32::MAX / 1000);
+    }
+
+    #[test]
+    fn test_operation_lengths() {
+        use embedded_hal::spi::Operation;
+
+        let (mut r1, mut r2, mut buf) = ([0; 3], [0; 2], [0; 4]);
+        let ops = [
+            Operation::Read(&mut r1),
+            Operation::Write(&[1, 2]),
+            Operation::Transfer(&mut r2, &[1, 2, 3, 4, 5]),
+            Operation::TransferInPlace(&mut buf),
+            Operation::DelayNs(1000),
+        ];
+        assert_eq!(operation_lengths(&ops), (2 + 5 + 4, 3 + 2 + 4));
+
+        let mut stats = Stats::default();
+        stats.record(11, 9);
+        stats.record(1, 0);
+        assert_eq!((stats.written, stats.read, stats.transfers), (12, 9, 2));
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err = SPIError::from(io::Error::from_raw_os_error(nix::libc::EINVAL));
+        assert_eq!(io::Error::from(err).raw_os_error(), Some(nix::libc::EINVAL));
+    }
+
+    #[test]
+    fn test_read_transfer() {
+        let mut buf = [0; 4];
+        read_transfer(&mut &[1, 2, 3, 4, 5][..], &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut reader = &[1, 2][..];
+        let err = read_transfer(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "SPI read returned 2 of 4 bytes");
+    }
+
+    #[test]
+    fn test_open_file() {
+        use std::io::{Read, Write};
+
+        let path = std::env::temp_dir().join(format!("spidev-open-{}", std::process::id()));
+        std::fs::write(&path, [1]).unwrap();
+        let mut buf = [0];
+
+        let spidev = open_file(&path, true, false).unwrap();
+        assert!(spidev.inner().write(&[2]).is_err());
+        assert_eq!(spidev.inner().read(&mut buf).unwrap(), 1);
+
+        let spidev = open_file(&path, false, true).unwrap();
+        assert!(spidev.inner().read(&mut buf).is_err());
+        assert_eq!(spidev.inner().write(&[2]).unwrap(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_check_bits_per_word() {
+        assert!(check_bits_per_word(1).is_ok());
+        assert!(check_bits_per_word(32).is_ok());
+        for bits in [0, 33] {
+            let err = check_bits_per_word(bits).unwrap_err();
+            assert_eq!(err.inner().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_word_sizes() {
+        assert_eq!(
+            [1, 7, 8, 9, 16, 17, 32].map(word_bytes),
+            [1, 1, 1, 2, 2, 4, 4]
+        );
+
+        let mut settings = BusSettings {
+            max_transfer_size: 4095,
+            word_bytes: 2,
+            ..BusSettings::default()
+        };
+        assert_eq!(settings.chunk_size(), 4094);
+        assert!(settings.check_words(6).is_ok());
+        let err = settings.check_words(5).unwrap_err();
+        assert_eq!(err.inner().kind(), io::ErrorKind::InvalidInput);
+
+        settings.max_transfer_size = 3;
+        settings.word_bytes = 4;
+        assert_eq!(settings.chunk_size(), 4);
+    }
+
+    #[test]
+    fn test_restore() {
+        /// Same shape as `SpidevDevice::with_speed`, on a plain speed value
+        fn with_speed<R>(speed: &mut u32, hz: u32, f: impl FnOnce(&mut u32) -> R) -> R {
+            let previous = std::mem::replace(speed, hz);
+            let mut guard = Restore::new(speed, move |speed: &mut u32| *speed = previous);
+            f(&mut guard)
+        }
+
+        let mut speed = 1_000_000;
+        let res = with_speed(&mut speed, 400_000, |speed| {
+            assert_eq!(*speed, 400_000);
+            Ok::<_, ()>(1)
+        });
+        assert_eq!(res, Ok(1));
+        assert_eq!(speed, 1_000_000);
+
+        let res = with_speed(&mut speed, 400_000, |_| Err::<(), _>("failed"));
+        assert_eq!(res, Err("failed"));
+        assert_eq!(speed, 1_000_000);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            with_speed(&mut speed, 400_000, |_| panic!("driver bug"))
+        }));
+        assert!(res.is_err());
+        assert_eq!(speed, 1_000_000);
+    }
+
+    #[test]
+    fn test_swap_config() {
+        /// Device whose configuration is written in two steps, the second failing for odd
+        /// values
+        #[derive(Default)]
+        struct Device {
+            mode: u8,
+            speed: u32,
+            transactions: Vec<(u8, u32)>,
+        }
+
+        fn read(dev: &Device) -> Result<(u8, u32), &'static str> {
+            Ok((dev.mode, dev.speed))
+        }
+
+        fn write(dev: &mut Device, &(mode, speed): &(u8, u32)) -> Result<(), &'static str> {
+            dev.mode = mode;
+            if speed % 2 == 1 {
+                return Err("unsupported speed");
+            }
+            dev.speed = speed;
+            Ok(())
+        }
+
+        fn transaction_with_config(
+            dev: &mut Device,
+            config: (u8, u32),
+            fail: bool,
+        ) -> Result<(), &'static str> {
+            let mut guard = swap_config(dev, config, read, write)?;
+            let config = read(&guard)?;
+            guard.transactions.push(config);
+            if fail {
+                Err("transfer failed")
+            } else {
+                Ok(())
+            }
+        }
+
+        let mut dev = Device {
+            mode: 0,
+            speed: 1_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            transaction_with_config(&mut dev, (3, 400_000), false),
+            Ok(())
+        );
+        assert_eq!((dev.mode, dev.speed), (0, 1_000_000));
+
+        // Restored after a failed transaction
+        let res = transaction_with_config(&mut dev, (3, 400_000), true);
+        assert_eq!(res, Err("transfer failed"));
+        assert_eq!((dev.mode, dev.speed), (0, 1_000_000));
+
+        // Restored after a partly applied configuration, without a transaction
+        let res = transaction_with_config(&mut dev, (3, 400_001), false);
+        assert_eq!(res, Err("unsupported speed"));
+        assert_eq!((dev.mode, dev.speed), (0, 1_000_000));
+
+        assert_eq!(dev.transactions, [(3, 400_000), (3, 400_000)]);
+    }
+
+    #[test]
+    fn test_apply_to_mode() {
+        use spidev::SpiModeFlags;
+
+        let config = SpiConfig::from_mode(SpiModeFlags::SPI_MODE_1.bits() as u8, 0, 8);
+        // SPI_READY, dual and quad transfer flags, and 0x6000 for SPI_{TX,RX}_OCTAL
+        let kept =
+            (SpiModeFlags::SPI_READY | SpiModeFlags::SPI_TX_DUAL | SpiModeFlags::SPI_RX_QUAD)
+                .bits()
+                | 0x6000;
+        let mode = (SpiModeFlags::SPI_MODE_2 | SpiModeFlags::SPI_LSB_FIRST).bits();
+        assert_eq!(
+            config.apply_to_mode(mode | kept),
+            SpiModeFlags::SPI_MODE_1.bits() | kept
+        );
+        assert_eq!(SpiConfig::from_mode(0x7f, 0, 8).apply_to_mode(0), 0x7f);
+        assert_eq!(SpiConfig::from_mode(0, 0, 8).apply_to_mode(!0), !0x7f);
+    }
+
+    #[test]
+    fn test_with_cs_high() {
+        use spidev::SpiModeFlags;
+
+        let cs_high = SpiModeFlags::SPI_CS_HIGH.bits();
+        let mode = (SpiModeFlags::SPI_MODE_3 | SpiModeFlags::SPI_LSB_FIRST).bits();
+        assert_eq!(with_cs_high(mode, true), mode | cs_high);
+        assert_eq!(with_cs_high(mode | cs_high, true), mode | cs_high);
+        assert_eq!(with_cs_high(mode | cs_high, false), mode);
+        assert_eq!(with_cs_high(0xff, false), 0xff & !cs_high);
+        // Dual, quad and octal transfer flags are kept, 0x6000 being SPI_{TX,RX}_OCTAL
+        let wide = (SpiModeFlags::SPI_TX_QUAD | SpiModeFlags::SPI_RX_DUAL).bits() | 0x6000;
+        assert_eq!(with_cs_high(wide | mode, true), wide | mode | cs_high);
+        assert_eq!(with_cs_high(!0, false), !cs_high);
+    }
+
+    #[test]
+    fn test_from_bus_cs_missing() {
+        let err = match SpidevDevice::from_bus_cs(u32::MAX, 1) {
+            Err(err) => io::Error::from(err),
+            Ok(_) => panic!("expected an error"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("/dev/spidev4294967295.1"));
+    }
+
+    #[test]
+    fn test_is_on_bus() {
+        assert!(is_on_bus(Path::new("/dev/spidev0.0"), 0));
+        assert!(is_on_bus(Path::new("/dev/spidev0.12"), 0));
+        assert!(is_on_bus(Path::new("/dev/spidev10.1"), 10));
+        assert!(!is_on_bus(Path::new("/dev/spidev10.1"), 1));
+        assert!(!is_on_bus(Path::new("/dev/spidev1.1"), 10));
+        assert!(!is_on_bus(Path::new("/dev/spidev1."), 1));
+        assert!(!is_on_bus(Path::new("/dev/spidev1.x"), 1));
+    }
+
+    #[test]
+    fn test_config_from_mode() {
+        use spidev::SpiModeFlags;
+
+        let mode = SpiModeFlags::SPI_MODE_2 | SpiModeFlags::SPI_LSB_FIRST | SpiModeFlags::SPI_NO_CS;
+        let config = SpiConfig::from_mode(mode.bits() as u8, 1_000_000, 8);
+        assert_eq!(
+            config,
+            SpiConfig {
+                cpol: true,
+                cpha: false,
+                cs_active_high: false,
+                lsb_first: true,
+                three_wire: false,
+                no_cs: true,
+                loop_back: false,
+                max_speed_hz: 1_000_000,
+                bits_per_word: 8,
+            }
+        );
+
+        let all = SpiConfig::from_mode(0xff, 0, 16);
+        assert!(all.cpol && all.cpha && all.cs_active_high && all.lsb_first);
+        assert!(all.three_wire && all.no_cs && all.loop_back);
+    }
+
+    #[test]
+    fn test_check_half_duplex() {
+        use embedded_hal::spi::Operation;
+
+        let three_wire = spidev::SpiModeFlags::SPI_3WIRE.bits();
+        let mode = spidev::SpiModeFlags::SPI_MODE_3.bits();
+        let mut buf = [0; 2];
+        let half = [
+            Operation::Write(&[1, 2]),
+            Operation::DelayNs(10),
+            Operation::Read(&mut buf),
+        ];
+        let settings = DeviceSettings::default();
+        assert!(check_half_duplex(&half, &settings, mode).is_ok());
+        assert!(check_half_duplex(&half, &settings, mode | three_wire).is_ok());
+
+        let (mut r, mut b) = ([0; 2], [0; 2]);
+        for full in [
+            [Operation::Transfer(&mut r, &[1, 2])],
+            [Operation::TransferInPlace(&mut b)],
+        ] {
+            assert!(check_half_duplex(&full, &settings, mode).is_ok());
+            let err = check_half_duplex(&full, &settings, mode | three_wire).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_check_duplex_cached_mode() {
+        use embedded_hal::spi::Operation;
+
+        // Not a spidev device, so querying the mode fails
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let mut dev = SpidevDevice::new(spidev::Spidev::new(file));
+        let mut buf = [0; 2];
+        let full = [Operation::TransferInPlace(&mut buf)];
+        assert!(check_duplex(&mut dev, &[Operation::Write(&[1])]).is_ok());
+        assert!(check_duplex(&mut dev, &full).is_err());
+
+        // A known mode is not queried again
+        let three_wire = spidev::SpiModeFlags::SPI_3WIRE.bits();
+        dev.1.mode = Some(0);
+        assert!(check_duplex(&mut dev, &full).is_ok());
+        dev.1.mode = Some(three_wire);
+        let err = io::Error::from(check_duplex(&mut dev, &full).unwrap_err());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Until the inner `Spidev` is borrowed mutably
+        let _ = &mut *dev;
+        assert_eq!(dev.1.mode, None);
+        assert!(check_duplex(&mut dev, &full).is_err());
+
+        // Reads clock out the dummy byte once it is set, so they need full duplex
+        dev.1.mode = Some(three_wire);
+        let read = [Operation::Read(&mut buf)];
+        assert!(check_duplex(&mut dev, &read).is_ok());
+        dev.set_dummy_byte(0xFF);
+        let err = io::Error::from(check_duplex(&mut dev, &read).unwrap_err());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_chunk_ranges() {
+        assert_eq!(chunk_ranges(0, 4).count(), 0);
+        assert!(chunk_ranges(4, 4).eq(std::iter::once(0..4)));
+        assert_eq!(chunk_ranges(10, 4).collect::<Vec<_>>(), [0..4, 4..8, 8..10]);
+    }
+
+    #[test]
+    fn test_word_delay_pad() {
+        // `word_delay_usecs` is the third of the four bytes spidev exposes as `pad`
+        assert_eq!(word_delay_pad(0), 0);
+        assert_eq!(word_delay_pad(42).to_ne_bytes(), [0, 0, 42, 0]);
+    }
+}
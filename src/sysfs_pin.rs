@@ -3,7 +3,11 @@
 //! [`embedded-hal`]: https://docs.rs/embedded-hal
 
 use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Newtype around [`sysfs_gpio::Pin`] that implements the `embedded-hal` traits
 ///
@@ -28,6 +32,25 @@ impl SysfsPin {
         sysfs_gpio::Pin::from_path(path).map(SysfsPin)
     }
 
+    /// Wait until the `value` file of this pin exists and can be opened for reading and
+    /// writing, for at most `timeout`.
+    ///
+    /// After a pin is exported, the kernel creates its sysfs files asynchronously, and udev
+    /// may only then adjust their permissions, so using the pin right after
+    /// [`export`][0] can fail. Call this in between when managing the export yourself. The
+    /// file is checked every 10 milliseconds.
+    ///
+    /// If the file is still missing or inaccessible once `timeout` elapsed, an I/O error of
+    /// kind [`io::ErrorKind::TimedOut`] is returned, stating the reason of the last failed
+    /// attempt. Other errors are returned right away.
+    ///
+    /// [0]: https://docs.rs/sysfs_gpio/0.6.0/sysfs_gpio/struct.Pin.html#method.export
+    pub fn wait_exported(&self, timeout: Duration) -> Result<(), SysfsPinError> {
+        let value = format!("/sys/class/gpio/gpio{}/value", self.0.get_pin_num());
+        wait_accessible(Path::new(&value), timeout, EXPORT_POLL_INTERVAL)
+            .map_err(|e| SysfsPinError::from(sysfs_gpio::Error::Io(e)))
+    }
+
     /// Convert this pin to an input pin
     pub fn into_input_pin(self) -> Result<SysfsPin, sysfs_gpio::Error> {
         self.set_direction(sysfs_gpio::Direction::In)?;
@@ -47,6 +70,43 @@ impl SysfsPin {
     }
 }
 
+/// Interval at which [`SysfsPin::wait_exported`] checks the `value` file
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wait until `path` can be opened for reading and writing, checking it every `interval`
+/// for at most `timeout`.
+///
+/// Only missing files and lacking permissions are waited out; other errors are returned
+/// right away.
+fn wait_accessible(path: &Path, timeout: Duration, interval: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let err = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        if !matches!(
+            err.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+        ) {
+            return Err(err);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{} not accessible after {:?}: {}",
+                    path.display(),
+                    timeout,
+                    err
+                ),
+            ));
+        }
+        thread::sleep(interval.min(deadline - now));
+    }
+}
+
 /// Error type wrapping [sysfs_gpio::Error](sysfs_gpio::Error) to implement [embedded_hal::digital::Error]
 #[derive(Debug)]
 pub struct SysfsPinError {
@@ -127,6 +187,111 @@ impl embedded_hal::digital::InputPin for SysfsPin {
     }
 }
 
+/// Edge waits run a blocking `poll` on the `value` file of the pin in a helper thread, which
+/// hands the result over to the waiting task through a channel. This works with any async
+/// runtime, but each wait costs a thread, spawned when the wait starts and ending with it, so
+/// it suits occasional waits on a few pins rather than high event rates, for which the
+/// character device and [`CdevPin`](crate::CdevPin) are better suited.
+///
+/// Starting a wait sets the `edge` attribute of the pin, so the pin must be an input whose
+/// GPIO can generate interrupts. Edges and levels are those of the pin, as reported by
+/// [`InputPin::is_high`](embedded_hal::digital::InputPin::is_high), regardless of its
+/// `active_low` setting. Edges occurring before a wait starts are not reported. When a wait
+/// is cancelled by dropping its future, its thread notices within 100 milliseconds and ends.
+#[cfg(feature = "async-tokio")]
+impl embedded_hal_async::digital::Wait for SysfsPin {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait(sysfs_gpio::Edge::RisingEdge, Some(true)).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait(sysfs_gpio::Edge::FallingEdge, Some(false)).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(sysfs_gpio::Edge::RisingEdge, None).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(sysfs_gpio::Edge::FallingEdge, None).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait(sysfs_gpio::Edge::BothEdges, None).await
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl SysfsPin {
+    /// Wait in a helper thread for `edge`, or return right away if the pin is at `level`.
+    async fn wait(
+        &mut self,
+        edge: sysfs_gpio::Edge,
+        level: Option<bool>,
+    ) -> Result<(), SysfsPinError> {
+        let pin = self.0;
+        let active_low = pin.get_active_low()?;
+        pin.set_edge(logical_edge(edge, active_low))?;
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        thread::Builder::new()
+            .name(format!("gpio{}-wait", pin.get_pin_num()))
+            .spawn(move || {
+                let res = wait_blocking(pin, active_low, level, || tx.is_closed());
+                let _ = tx.send(res);
+            })
+            .map_err(sysfs_gpio::Error::Io)?;
+        rx.await
+            .unwrap_or_else(|_| Err(sysfs_gpio::Error::Unexpected("wait thread panicked".into())))
+            .map_err(SysfsPinError::from)
+    }
+}
+
+/// Interval at which edge wait threads check whether their wait was cancelled, in
+/// milliseconds
+#[cfg(feature = "async-tokio")]
+const WAIT_CANCEL_POLL_MS: isize = 100;
+
+/// Block until an edge of `pin`, as set in its `edge` attribute, occurs, or return right
+/// away if the pin is at `level`. Gives up without error once `cancelled` returns `true`.
+#[cfg(feature = "async-tokio")]
+fn wait_blocking(
+    pin: sysfs_gpio::Pin,
+    active_low: bool,
+    level: Option<bool>,
+    cancelled: impl Fn() -> bool,
+) -> sysfs_gpio::Result<()> {
+    let mut poller = pin.get_poller()?;
+    // The value file was never read since it was opened, which the first poll reports
+    // right away; consume that before looking for actual edges.
+    poller.poll(0)?;
+    if let Some(level) = level {
+        let high = (pin.get_value()? != 0) != active_low;
+        if high == level {
+            return Ok(());
+        }
+    }
+    while !cancelled() {
+        if poller.poll(WAIT_CANCEL_POLL_MS)?.is_some() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// The `edge` attribute value selecting the pin `edge` of a pin, as the kernel swaps rising
+/// and falling edges of active-low pins.
+#[cfg(feature = "async-tokio")]
+fn logical_edge(edge: sysfs_gpio::Edge, active_low: bool) -> sysfs_gpio::Edge {
+    use sysfs_gpio::Edge;
+
+    match (edge, active_low) {
+        (Edge::RisingEdge, true) => Edge::FallingEdge,
+        (Edge::FallingEdge, true) => Edge::RisingEdge,
+        (edge, _) => edge,
+    }
+}
+
 impl core::ops::Deref for SysfsPin {
     type Target = sysfs_gpio::Pin;
 
@@ -140,3 +305,50 @@ impl core::ops::DerefMut for SysfsPin {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "async-tokio")]
+    #[test]
+    fn test_logical_edge() {
+        use sysfs_gpio::Edge;
+
+        assert_eq!(logical_edge(Edge::RisingEdge, false), Edge::RisingEdge);
+        assert_eq!(logical_edge(Edge::RisingEdge, true), Edge::FallingEdge);
+        assert_eq!(logical_edge(Edge::FallingEdge, true), Edge::RisingEdge);
+        assert_eq!(logical_edge(Edge::BothEdges, true), Edge::BothEdges);
+    }
+
+    #[test]
+    fn test_wait_accessible() {
+        let dir = std::env::temp_dir().join(format!(
+            "linux-embedded-hal-sysfs-pin-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let value = dir.join("value");
+        let _ = std::fs::remove_file(&value);
+        let interval = Duration::from_millis(1);
+
+        let err = wait_accessible(&value, Duration::from_millis(5), interval).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let creator = {
+            let value = value.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                std::fs::write(value, "0\n").unwrap();
+            })
+        };
+        wait_accessible(&value, Duration::from_secs(5), interval).unwrap();
+        creator.join().unwrap();
+
+        // Anything but a missing or inaccessible file is reported at once
+        let err = wait_accessible(&dir, Duration::from_secs(5), interval).unwrap_err();
+        assert_ne!(err.kind(), io::ErrorKind::TimedOut);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,6 +5,10 @@
 use core::convert::Infallible;
 use std::time::{Duration, Instant};
 
+use embedded_hal::delay::DelayNs;
+
+use crate::Delay;
+
 /// Marker trait that indicates that a timer is periodic
 pub trait Periodic {}
 
@@ -97,10 +101,23 @@ impl<T: CountDown> CountDown for &mut T {
 /// A periodic timer based on [`std::time::Instant`][instant], which is a
 /// monotonically nondecreasing clock.
 ///
+/// Each period is scheduled relative to the deadline of the previous one rather
+/// than to the moment `wait` happened to observe it, so the polling overhead
+/// does not accumulate as drift over many periods. If `wait` is polled late by
+/// more than a whole period, the missed periods are skipped: `wait` returns
+/// `Ok` once and the next deadline is the next one on the original schedule,
+/// instead of returning `Ok` in a burst for every missed period.
+///
+/// The time actually elapsed between successive successful `wait`s is
+/// recorded, see [`SysTimer::last_period`] and [`SysTimer::jitter`].
+///
 /// [instant]: https://doc.rust-lang.org/std/time/struct.Instant.html
 pub struct SysTimer {
     start: Instant,
     duration: Duration,
+    /// When `wait` last succeeded, or the timer was started
+    last_ok: Option<Instant>,
+    last_period: Option<Duration>,
 }
 
 impl SysTimer {
@@ -112,8 +129,38 @@ impl SysTimer {
         SysTimer {
             start: Instant::now(),
             duration: Duration::from_millis(0),
+            last_ok: None,
+            last_period: None,
         }
     }
+
+    /// The time measured between the last two successful `wait`s, or between
+    /// `start` and the first one, or `None` if `wait` hasn't succeeded since
+    /// the timer was started.
+    ///
+    /// This is when `wait` observed the deadlines, so it includes the
+    /// scheduling and polling latency of the caller, unlike the schedule
+    /// itself, which doesn't drift. It shows how regularly a control loop
+    /// actually runs, which on Linux without a realtime kernel varies with
+    /// the system load. A period spanning skipped periods measures all of them.
+    pub fn last_period(&self) -> Option<Duration> {
+        self.last_period
+    }
+
+    /// How far [`SysTimer::last_period`] is from the requested period, early
+    /// or late, or `None` if no period was measured yet.
+    ///
+    /// Consistently large values suggest that the loop needs a realtime
+    /// kernel, a higher priority, or a hardware timer.
+    pub fn jitter(&self) -> Option<Duration> {
+        let period = self.last_period?;
+        // `Duration::abs_diff` needs a newer compiler
+        Some(
+            period
+                .checked_sub(self.duration)
+                .unwrap_or_else(|| self.duration - period),
+        )
+    }
 }
 
 impl Default for SysTimer {
@@ -132,13 +179,22 @@ impl CountDown for SysTimer {
     {
         self.start = Instant::now();
         self.duration = count.into();
+        self.last_ok = Some(self.start);
+        self.last_period = None;
         Ok(())
     }
 
     fn wait(&mut self) -> nb::Result<(), Self::Error> {
-        if (Instant::now() - self.start) >= self.duration {
-            // Restart the timer to fulfill the contract by `Periodic`
-            self.start = Instant::now();
+        let now = Instant::now();
+        let elapsed = now - self.start;
+        if elapsed >= self.duration {
+            // Restart the timer to fulfill the contract by `Periodic`. The new
+            // period starts at the last deadline on the schedule (skipping any
+            // fully missed periods), not at `now`, so that polling latency
+            // does not accumulate.
+            self.start = now - missed_remainder(elapsed, self.duration);
+            self.last_period = self.last_ok.map(|last| now - last);
+            self.last_ok = Some(now);
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
@@ -148,6 +204,198 @@ impl CountDown for SysTimer {
 
 impl Periodic for SysTimer {}
 
+/// A one-shot timer based on [`std::time::Instant`][instant].
+///
+/// Unlike [`SysTimer`], this timer is not [`Periodic`]: once the count down has finished,
+/// `wait` keeps returning `Ok` without restarting, until `start` is called again. This suits
+/// state machines that poll a timeout and expect it to stay fired.
+///
+/// [instant]: https://doc.rust-lang.org/std/time/struct.Instant.html
+pub struct OneShotTimer {
+    start: Instant,
+    duration: Duration,
+}
+
+impl OneShotTimer {
+    /// Create a new timer instance.
+    ///
+    /// The `duration` will be initialized to 0, so the timer is already
+    /// expired until `start` is called with your desired timer duration.
+    pub fn new() -> OneShotTimer {
+        OneShotTimer {
+            start: Instant::now(),
+            duration: Duration::from_millis(0),
+        }
+    }
+}
+
+impl Default for OneShotTimer {
+    fn default() -> OneShotTimer {
+        OneShotTimer::new()
+    }
+}
+
+impl CountDown for OneShotTimer {
+    type Error = Infallible;
+    type Time = Duration;
+
+    fn start<T>(&mut self, count: T) -> Result<(), Self::Error>
+    where
+        T: Into<Self::Time>,
+    {
+        self.start = Instant::now();
+        self.duration = count.into();
+        Ok(())
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Self::Error> {
+        if (Instant::now() - self.start) >= self.duration {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// A periodic timer like [`SysTimer`], whose `wait` sleeps until the count down finishes.
+///
+/// Instead of returning `WouldBlock` until the deadline, `wait` sleeps for the remaining time
+/// using a [`DelayNs`] implementation, [`Delay`] by default, and then returns `Ok`. Blocking
+/// on it with `nb::block!` therefore doesn't spin the CPU, which saves power, at the cost of
+/// `wait` never returning `WouldBlock`: it is not suited to polling several timers or other
+/// events in the same loop. Periods are scheduled like those of [`SysTimer`].
+pub struct BlockingCountDown<D = Delay> {
+    delay: D,
+    start: Instant,
+    duration: Duration,
+}
+
+impl BlockingCountDown {
+    /// Create a new timer instance sleeping with [`Delay`].
+    ///
+    /// The `duration` will be initialized to 0, so make sure to call `start`
+    /// with your desired timer duration before calling `wait`.
+    pub fn new() -> Self {
+        BlockingCountDown::with_delay(Delay)
+    }
+}
+
+impl<D: DelayNs> BlockingCountDown<D> {
+    /// Create a new timer instance sleeping with `delay`.
+    pub fn with_delay(delay: D) -> Self {
+        BlockingCountDown {
+            delay,
+            start: Instant::now(),
+            duration: Duration::from_millis(0),
+        }
+    }
+}
+
+impl Default for BlockingCountDown {
+    fn default() -> Self {
+        BlockingCountDown::new()
+    }
+}
+
+impl<D: DelayNs> CountDown for BlockingCountDown<D> {
+    type Error = Infallible;
+    type Time = Duration;
+
+    fn start<T>(&mut self, count: T) -> Result<(), Self::Error>
+    where
+        T: Into<Self::Time>,
+    {
+        self.start = Instant::now();
+        self.duration = count.into();
+        Ok(())
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Self::Error> {
+        loop {
+            let now = Instant::now();
+            let elapsed = now - self.start;
+            match self.duration.checked_sub(elapsed) {
+                Some(remaining) if !remaining.is_zero() => {
+                    // Round up so that a sleep is never shorter than the remaining time
+                    let us =
+                        remaining.as_micros() + u128::from(remaining.subsec_nanos() % 1000 != 0);
+                    self.delay.delay_us(us.min(u32::MAX.into()) as u32);
+                }
+                _ => {
+                    self.start = now - missed_remainder(elapsed, self.duration);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<D: DelayNs> Periodic for BlockingCountDown<D> {}
+
+/// A periodic timer for async code, sleeping with [`tokio::time::sleep_until`] until each
+/// count down finishes.
+///
+/// This is the async counterpart of [`SysTimer`]: instead of polling `wait` until it stops
+/// returning `WouldBlock`, await [`AsyncSysTimer::wait`], which doesn't spin. Periods are
+/// scheduled like those of [`SysTimer`], relative to the previous deadline so that they
+/// don't drift, and periods missed by waiting late are skipped. As with any tokio timer,
+/// each wait may end up to a millisecond or so late, without affecting later deadlines.
+#[cfg(feature = "async-tokio")]
+pub struct AsyncSysTimer {
+    start: tokio::time::Instant,
+    duration: Duration,
+}
+
+#[cfg(feature = "async-tokio")]
+impl AsyncSysTimer {
+    /// Create a new timer instance.
+    ///
+    /// The `duration` will be initialized to 0, so make sure to call `start`
+    /// with your desired timer duration before calling `wait`.
+    pub fn new() -> Self {
+        AsyncSysTimer {
+            start: tokio::time::Instant::now(),
+            duration: Duration::from_millis(0),
+        }
+    }
+
+    /// Start a new count down of `count`, after which it restarts periodically.
+    pub fn start(&mut self, count: Duration) {
+        self.start = tokio::time::Instant::now();
+        self.duration = count;
+    }
+
+    /// Wait until the count down finishes, and start the next period.
+    pub async fn wait(&mut self) {
+        tokio::time::sleep_until(self.start + self.duration).await;
+        let now = tokio::time::Instant::now();
+        self.start = now - missed_remainder(now - self.start, self.duration);
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl Default for AsyncSysTimer {
+    fn default() -> Self {
+        AsyncSysTimer::new()
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl Periodic for AsyncSysTimer {}
+
+/// Returns how far past the most recent deadline `elapsed` is, for a timer
+/// with the given `period`.
+fn missed_remainder(elapsed: Duration, period: Duration) -> Duration {
+    let period = period.as_nanos();
+    if period == 0 {
+        return Duration::from_nanos(0);
+    }
+    // The remainder is smaller than `period`, which came from a `Duration`,
+    // so it always fits.
+    let rem = elapsed.as_nanos() % period;
+    Duration::new((rem / 1_000_000_000) as u64, (rem % 1_000_000_000) as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -179,8 +427,144 @@ mod tests {
         assert!(duration_ms_1 < 500);
         nb::block!(timer.wait()).unwrap();
         let after2 = Instant::now();
-        let duration_ms_2 = (after2 - after1).as_millis();
-        assert!(duration_ms_2 >= 100);
-        assert!(duration_ms_2 < 500);
+        // The second period is scheduled from the first deadline, not from
+        // when the first `wait` returned.
+        let duration_ms_2 = (after2 - before).as_millis();
+        assert!(duration_ms_2 >= 200);
+        assert!(duration_ms_2 < 600);
+    }
+
+    /// Ensure that repeated periods don't accumulate drift.
+    #[test]
+    fn test_periodic_no_drift() {
+        const PERIODS: u32 = 30;
+        let period = Duration::from_millis(10);
+        let mut timer = SysTimer::new();
+        let before = Instant::now();
+        timer.start(period).unwrap();
+        for _ in 0..PERIODS {
+            // Polling every 3 ms sees each deadline up to 3 ms late, which would add up to
+            // 360 ms or more if each period restarted from when `wait` saw the last deadline,
+            // instead of 300 ms on schedule.
+            while timer.wait().is_err() {
+                std::thread::sleep(Duration::from_millis(3));
+            }
+        }
+        let elapsed = Instant::now() - before;
+        assert!(elapsed >= period * PERIODS);
+        assert!(elapsed < Duration::from_millis(330));
+    }
+
+    /// Ensure that periods missed by polling late are skipped rather than
+    /// returned in a burst, and that the schedule is kept.
+    #[test]
+    fn test_periodic_late_poll() {
+        let period = Duration::from_millis(100);
+        let mut timer = SysTimer::new();
+        let before = Instant::now();
+        timer.start(period).unwrap();
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(timer.wait(), Ok(()));
+        assert_eq!(timer.wait(), Err(nb::Error::WouldBlock));
+        nb::block!(timer.wait()).unwrap();
+        let elapsed_ms = (Instant::now() - before).as_millis();
+        // Restarting the period when `wait` observed the late deadline would end at 350 ms
+        assert!(elapsed_ms >= 300);
+        assert!(elapsed_ms < 350);
+    }
+
+    /// Ensure that the measured periods follow the actual calls to `wait`.
+    #[test]
+    fn test_last_period() {
+        // The upper bounds leave a whole period for the wakeup latency of a loaded system
+        let period = Duration::from_millis(40);
+        let mut timer = SysTimer::new();
+        timer.start(period).unwrap();
+        assert_eq!(timer.last_period(), None);
+        assert_eq!(timer.jitter(), None);
+
+        nb::block!(timer.wait()).unwrap();
+        let first = timer.last_period().unwrap();
+        assert!(first >= period && first < period * 2);
+        assert!(timer.jitter().unwrap() < period);
+
+        // Polled 60 ms late: the period after it is half as long, to keep the schedule
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(timer.wait(), Ok(()));
+        assert!(timer.last_period().unwrap() >= Duration::from_millis(100));
+        assert!(timer.jitter().unwrap() >= Duration::from_millis(60));
+        nb::block!(timer.wait()).unwrap();
+        assert!(timer.last_period().unwrap() < period);
+        assert!(timer.jitter().unwrap() > Duration::ZERO);
+
+        timer.start(period).unwrap();
+        assert_eq!(timer.last_period(), None);
+    }
+
+    /// Ensure that a one-shot timer stays expired once it has fired.
+    #[test]
+    fn test_one_shot() {
+        let mut timer = OneShotTimer::new();
+        let before = Instant::now();
+        timer.start(Duration::from_millis(50)).unwrap();
+        assert_eq!(timer.wait(), Err(nb::Error::WouldBlock));
+        nb::block!(timer.wait()).unwrap();
+        let duration_ms = (Instant::now() - before).as_millis();
+        assert!(duration_ms >= 50);
+        assert!(duration_ms < 500);
+        assert_eq!(timer.wait(), Ok(()));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(timer.wait(), Ok(()));
+
+        // Restarting arms the timer again
+        timer.start(Duration::from_millis(50)).unwrap();
+        assert_eq!(timer.wait(), Err(nb::Error::WouldBlock));
+    }
+
+    /// Ensure that a blocking count down sleeps through the whole period in a
+    /// single `wait` call instead of being polled in a loop.
+    #[test]
+    fn test_blocking_count_down() {
+        let mut timer = BlockingCountDown::new();
+        let before = Instant::now();
+        timer.start(Duration::from_millis(100)).unwrap();
+        for period in 1..=2 {
+            let mut polls = 0;
+            nb::block!({
+                polls += 1;
+                timer.wait()
+            })
+            .unwrap();
+            assert_eq!(polls, 1);
+            let duration_ms = (Instant::now() - before).as_millis();
+            assert!(duration_ms >= 100 * period);
+            assert!(duration_ms < 100 * period + 400);
+        }
+    }
+
+    /// Ensure that the async timer keeps its period over several iterations.
+    #[cfg(feature = "async-tokio")]
+    #[tokio::test]
+    async fn test_async_periodic() {
+        const PERIODS: u32 = 10;
+        let period = Duration::from_millis(10);
+        let mut timer = AsyncSysTimer::new();
+        let before = Instant::now();
+        timer.start(period);
+        for i in 1..=PERIODS {
+            timer.wait().await;
+            let elapsed = Instant::now() - before;
+            assert!(elapsed >= period * i);
+            assert!(elapsed < period * i + Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_missed_remainder() {
+        let ms = Duration::from_millis;
+        assert_eq!(missed_remainder(ms(10), ms(10)), ms(0));
+        assert_eq!(missed_remainder(ms(13), ms(10)), ms(3));
+        assert_eq!(missed_remainder(ms(37), ms(10)), ms(7));
+        assert_eq!(missed_remainder(ms(5), ms(0)), ms(0));
     }
 }